@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Clock tells the time to the adapters measuring it, so that tests can substitute a ManualClock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// Sleeper blocks the adapters that wait for time to pass, so that tests can substitute a ManualClock.
+pub trait Sleeper: Send + Sync {
+    fn sleep(&self, duration: Duration);
+}
+
+/// SystemClock is the real, monotonic clock, sleeping blocks the thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl Sleeper for SystemClock {
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// ManualClock only moves when advance() is called, clones share the same time.
+///
+/// Sleeping on a ManualClock returns immediately after advancing it by the duration.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+impl Sleeper for ManualClock {
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
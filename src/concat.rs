@@ -0,0 +1,231 @@
+use std::fmt;
+use std::io::{self, Read};
+
+/// SourceError is an error met by a MultiReader, naming the source it came from.
+///
+/// Returned inside an io::Error of the same kind, see `downcast_ref::<SourceError>()`.
+#[derive(Debug)]
+pub struct SourceError {
+    /// The index of the failing source.
+    pub index: usize,
+    /// The bytes read from the failing source before the error.
+    pub offset: u64,
+    pub source: io::Error,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "source {} at offset {}: {}", self.index, self.offset, self.source)
+    }
+}
+
+impl std::error::Error for SourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<SourceError> for io::Error {
+    fn from(e: SourceError) -> io::Error {
+        io::Error::new(e.source.kind(), e)
+    }
+}
+
+type Sources<'a, R> = Box<dyn Iterator<Item = io::Result<R>> + 'a>;
+
+/// MultiReader reads its sources one after the other, like a chain of any length,
+/// and keeps track of which source is being read and how far.
+///
+/// Sources can be given upfront, or opened lazily by an iterator as the previous source reaches EOF.
+/// Errors reading or opening a source are returned as a SourceError naming the source.
+/// A source that failed to open is skipped by the next read, a source that failed to read is read again.
+pub struct MultiReader<'a, R = Box<dyn Read + 'a>> {
+    sources: Sources<'a, R>,
+    current: Option<R>,
+    index: usize,
+    offset: u64,
+    // the sources iterator is exhausted
+    done: bool,
+}
+
+impl<'a, R: Read + 'a> MultiReader<'a, R> {
+    pub fn new(sources: Vec<R>) -> MultiReader<'a, R> {
+        MultiReader::lazy(sources.into_iter().map(Ok))
+    }
+
+    /// Creates a reader over the sources produced by `sources`, each one is only opened
+    /// once the previous one is read to the end.
+    pub fn lazy<I>(sources: I) -> MultiReader<'a, R>
+    where
+        I: IntoIterator<Item = io::Result<R>>,
+        I::IntoIter: 'a,
+    {
+        MultiReader {
+            sources: Box::new(sources.into_iter()),
+            current: None,
+            index: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the index of the source being read. Once all sources are read it is the count of sources.
+    pub fn source_index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the bytes read from the source being read.
+    pub fn source_offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Gets a reference to the source being read, if it is open.
+    pub fn get_ref(&self) -> Option<&R> {
+        self.current.as_ref()
+    }
+
+    /// Gets a mutable reference to the source being read, if it is open. Reading from it directly is not tracked.
+    pub fn get_mut(&mut self) -> Option<&mut R> {
+        self.current.as_mut()
+    }
+
+    // moves on to the next source
+    fn next_source(&mut self) {
+        self.current = None;
+        self.index += 1;
+        self.offset = 0;
+    }
+}
+
+impl<'a, R: Read + 'a> Read for MultiReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let current = match self.current {
+                Some(ref mut current) => current,
+                None if self.done => return Ok(0),
+                None => match self.sources.next() {
+                    Some(Ok(source)) => self.current.insert(source),
+                    Some(Err(e)) => {
+                        let error = SourceError { index: self.index, offset: 0, source: e };
+                        self.next_source();
+                        return Err(error.into());
+                    }
+                    None => {
+                        self.done = true;
+                        return Ok(0);
+                    }
+                },
+            };
+            match current.read(buf) {
+                Ok(0) => self.next_source(),
+                Ok(size) => {
+                    self.offset += size as u64;
+                    return Ok(size);
+                }
+                Err(e) => return Err(SourceError { index: self.index, offset: self.offset, source: e }.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod test_multi_reader {
+        use crate::concat::{MultiReader, SourceError};
+        use std::io::{self, Read};
+
+        fn source_error(e: &io::Error) -> &SourceError {
+            e.get_ref().unwrap().downcast_ref::<SourceError>().unwrap()
+        }
+
+        #[test]
+        fn test_concat() {
+            let sources: Vec<Box<dyn Read>> = vec![
+                Box::new(&b"one "[..]),
+                Box::new(io::empty()),
+                Box::new(&b"two "[..]),
+                Box::new(&b"three"[..]),
+            ];
+            let mut reader = MultiReader::new(sources);
+            let mut out = String::new();
+            reader.read_to_string(&mut out).unwrap();
+            assert_eq!(out, "one two three");
+            assert_eq!(reader.source_index(), 4);
+            assert_eq!(reader.read(&mut [0u8; 4]).unwrap(), 0);
+        }
+
+        #[test]
+        fn test_position() {
+            let mut reader = MultiReader::new(vec![&b"abcd"[..], &b"efgh"[..]]);
+            let mut buf = [0u8; 3];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!((reader.source_index(), reader.source_offset()), (0, 3));
+            // a read does not span sources
+            assert_eq!(reader.read(&mut buf).unwrap(), 1);
+            assert_eq!(reader.read(&mut buf).unwrap(), 3);
+            assert_eq!((reader.source_index(), reader.source_offset()), (1, 3));
+            assert_eq!(reader.get_ref(), Some(&&b"h"[..]));
+        }
+
+        #[test]
+        fn test_lazy_open() {
+            let names = vec!["a", "missing", "c"];
+            let mut opened = Vec::new();
+            let opener = names.into_iter().map(|name| {
+                if name == "missing" {
+                    Err(io::Error::new(io::ErrorKind::NotFound, name))
+                } else {
+                    Ok(io::Cursor::new(name.repeat(3).into_bytes()))
+                }
+            });
+            let mut reader = MultiReader::lazy(opener);
+            let mut buf = [0u8; 8];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(size) => opened.extend_from_slice(&buf[..size]),
+                    Err(e) => {
+                        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+                        let error = source_error(&e);
+                        assert_eq!((error.index, error.offset), (1, 0));
+                        assert_eq!(e.to_string(), "source 1 at offset 0: missing");
+                    }
+                }
+            }
+            assert_eq!(opened, b"aaaccc");
+        }
+
+        struct FailingReader {
+            data: &'static [u8],
+        }
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+                if self.data.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt"));
+                }
+                self.data.read(buf)
+            }
+        }
+
+        #[test]
+        fn test_read_error() {
+            let sources: Vec<Box<dyn Read>> = vec![
+                Box::new(&b"fine"[..]),
+                Box::new(FailingReader { data: b"bad" }),
+            ];
+            let mut reader = MultiReader::new(sources);
+            let mut out = Vec::new();
+            let err = reader.read_to_end(&mut out).unwrap_err();
+            assert_eq!(out, b"finebad");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let error = source_error(&err);
+            assert_eq!((error.index, error.offset), (1, 3));
+            // the failing source is read again
+            assert!(reader.read(&mut [0u8; 4]).is_err());
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use super::{CaseMatching, Match, PatternError};
+
+// PatternSet is a byte trie over a list of patterns, probed one stream position at a time.
+//
+// Matching is leftmost-longest: the caller walks the stream from left to right and at each
+// position asks the trie for the longest pattern starting there. A probe either settles
+// (match / no match) or asks for more input, which is what keeps the streaming adapters bounded
+// to a window of max_len() bytes.
+
+pub(crate) enum Probe {
+    // pattern `index` matches the first `len` bytes of the input
+    Match { index: usize, len: usize },
+    // no pattern starts at the first byte of the input
+    NoMatch,
+    // a longer pattern could still match, more input is needed to decide
+    Incomplete,
+}
+
+struct Node {
+    // outgoing edges, sorted by byte
+    children: Vec<(u8, usize)>,
+    // the pattern ending at this node, if any
+    pattern: Option<usize>,
+}
+
+pub(crate) struct PatternSet {
+    // nodes[0] is the root, edges are over case folded bytes unless matching is case sensitive
+    nodes: Vec<Node>,
+    // starts[b] is true if some match may begin with byte b
+    starts: [bool; 256],
+    // the longest input a match can span
+    max_len: usize,
+    case: CaseMatching,
+}
+
+// the result of decoding one UTF-8 character
+enum Decoded {
+    Char(char, usize),
+    // input ends in the middle of a character
+    Short,
+    Invalid,
+}
+
+fn decode(input: &[u8]) -> Decoded {
+    let len = match input[0] {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return Decoded::Invalid,
+    };
+    if input.len() < len {
+        return if input[1..].iter().all(|b| b & 0xc0 == 0x80) { Decoded::Short } else { Decoded::Invalid };
+    }
+    match std::str::from_utf8(&input[..len]) {
+        Ok(s) => Decoded::Char(s.chars().next().unwrap(), len),
+        Err(_) => Decoded::Invalid,
+    }
+}
+
+// maps every character of a case insensitive class to the same character, using the case mappings of std
+// that have a single character result. This is Unicode simple case folding up to the choice of representative.
+fn fold(c: char) -> char {
+    fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    }
+    let upper = single(c.to_uppercase()).unwrap_or(c);
+    single(upper.to_lowercase()).unwrap_or(upper)
+}
+
+impl PatternSet {
+    // builds the trie, if the same pattern is given more than once the first index wins.
+    pub fn new<I, P>(patterns: I, case: CaseMatching) -> Result<PatternSet, PatternError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let mut set = PatternSet {
+            nodes: vec![Node { children: Vec::new(), pattern: None }],
+            // any byte can start a character folding into a pattern's first character
+            starts: [case == CaseMatching::UnicodeInsensitive; 256],
+            max_len: 0,
+            case,
+        };
+        for (index, pattern) in patterns.into_iter().enumerate() {
+            let pattern = pattern.as_ref();
+            if pattern.is_empty() {
+                return Err(PatternError::EmptyPattern(index));
+            }
+
+            let (folded, max_len) = match case {
+                CaseMatching::Sensitive => (pattern.to_vec(), pattern.len()),
+                CaseMatching::AsciiInsensitive => (pattern.to_ascii_lowercase(), pattern.len()),
+                CaseMatching::UnicodeInsensitive => {
+                    let pattern = std::str::from_utf8(pattern).map_err(|_| PatternError::InvalidUtf8(index))?;
+                    let folded: String = pattern.chars().map(fold).collect();
+                    // every input character matching a pattern character is at most 4 bytes long
+                    (folded.into_bytes(), 4 * pattern.chars().count())
+                }
+            };
+            let mut node = 0;
+            for &b in &folded {
+                node = match set.child(node, b) {
+                    Some(next) => next,
+                    None => set.add_child(node, b),
+                };
+            }
+            if set.nodes[node].pattern.is_none() {
+                set.nodes[node].pattern = Some(index);
+            }
+            set.starts[folded[0] as usize] = true;
+            set.starts[folded[0].to_ascii_uppercase() as usize] |= case == CaseMatching::AsciiInsensitive;
+            set.max_len = set.max_len.max(max_len);
+        }
+        Ok(set)
+    }
+
+    // length of the longest pattern, which is also the longest lookahead probe() can ask for.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    // finds the longest pattern that is a prefix of input.
+    //
+    // If eof is false and input ends while a longer pattern is still possible, Incomplete is returned
+    // even if a shorter pattern already matched.
+    #[inline]
+    pub fn probe(&self, input: &[u8], eof: bool) -> Probe {
+        match input.first() {
+            Some(&b) if self.starts[b as usize] => (),
+            Some(_) => return Probe::NoMatch,
+            None => return if eof { Probe::NoMatch } else { Probe::Incomplete },
+        }
+
+        let mut node = 0usize;
+        let mut longest = None;
+        let mut pos = 0;
+        while pos < input.len() {
+            let mut encoded = [0u8; 4];
+            let (folded, len): (&[u8], usize) = match self.case {
+                CaseMatching::Sensitive => (&input[pos..pos + 1], 1),
+                CaseMatching::AsciiInsensitive => {
+                    encoded[0] = input[pos].to_ascii_lowercase();
+                    (&encoded[..1], 1)
+                }
+                CaseMatching::UnicodeInsensitive => match decode(&input[pos..]) {
+                    Decoded::Char(c, len) => (fold(c).encode_utf8(&mut encoded).as_bytes(), len),
+                    Decoded::Short if !eof => return Probe::Incomplete,
+                    Decoded::Short | Decoded::Invalid => return Self::settle(longest),
+                },
+            };
+            for &b in folded {
+                node = match self.child(node, b) {
+                    Some(next) => next,
+                    None => return Self::settle(longest),
+                };
+            }
+            pos += len;
+            if let Some(index) = self.nodes[node].pattern {
+                longest = Some((index, pos));
+            }
+        }
+        if !eof && !self.nodes[node].children.is_empty() {
+            return Probe::Incomplete;
+        }
+        Self::settle(longest)
+    }
+
+    fn settle(longest: Option<(usize, usize)>) -> Probe {
+        match longest {
+            Some((index, len)) => Probe::Match { index, len },
+            None => Probe::NoMatch,
+        }
+    }
+
+    fn child(&self, node: usize, b: u8) -> Option<usize> {
+        let children = &self.nodes[node].children;
+        children
+            .binary_search_by_key(&b, |&(edge, _)| edge)
+            .ok()
+            .map(|i| children[i].1)
+    }
+
+    fn add_child(&mut self, node: usize, b: u8) -> usize {
+        let next = self.nodes.len();
+        self.nodes.push(Node { children: Vec::new(), pattern: None });
+        let children = &mut self.nodes[node].children;
+        let at = children.binary_search_by_key(&b, |&(edge, _)| edge).unwrap_err();
+        children.insert(at, (b, next));
+        next
+    }
+}
+
+// called with the match number and the stream offset of the match, appends the replacement to the output
+pub(crate) type ReplaceFn<'a> = Box<dyn FnMut(usize, usize, &mut Vec<u8>) + Send + 'a>;
+
+// Replacements produces the bytes substituted for a match.
+pub(crate) enum Replacements<'a> {
+    // one fixed replacement per pattern
+    Fixed(Vec<Vec<u8>>),
+    // computed for each match
+    With(ReplaceFn<'a>),
+}
+
+// Replacer rewrites a stream of bytes, substituting every pattern match with its replacement.
+pub(crate) struct Replacer<'a> {
+    patterns: PatternSet,
+    replacements: Replacements<'a>,
+
+    // number of input bytes consumed so far, the stream offset of the next process() input
+    consumed: usize,
+    // number of output bytes produced so far
+    produced: usize,
+    // number of matches replaced so far
+    match_count: usize,
+
+    // matches not yet taken by the caller, if recording is on
+    recorded: Option<Vec<Match>>,
+
+    // at most this many matches are replaced
+    max_count: Option<usize>,
+    // only matches within stream offsets start..end are replaced
+    start: usize,
+    end: Option<usize>,
+}
+
+impl<'a> Replacer<'a> {
+    pub fn new<I, P, N>(pairs: I) -> Result<Replacer<'a>, PatternError>
+    where
+        I: IntoIterator<Item = (P, N)>,
+        P: AsRef<[u8]>,
+        N: AsRef<[u8]>,
+    {
+        let (patterns, replacements): (Vec<Vec<u8>>, Vec<Vec<u8>>) = pairs
+            .into_iter()
+            .map(|(old, new)| (old.as_ref().to_vec(), new.as_ref().to_vec()))
+            .unzip();
+        let patterns = PatternSet::new(&patterns, CaseMatching::Sensitive)?;
+        Ok(Replacer::with_replacements(patterns, Replacements::Fixed(replacements)))
+    }
+
+    pub fn with_replacements(patterns: PatternSet, replacements: Replacements<'a>) -> Replacer<'a> {
+        Replacer {
+            patterns,
+            replacements,
+            consumed: 0,
+            produced: 0,
+            match_count: 0,
+            recorded: None,
+            max_count: None,
+            start: 0,
+            end: None,
+        }
+    }
+
+    // limits replacement to the first max_count matches lying within start..end of the stream.
+    pub fn with_limits(mut self, max_count: Option<usize>, start: usize, end: Option<usize>) -> Replacer<'a> {
+        self.max_count = max_count;
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    // true if the limits rule out any further replacement past stream offset `offset`.
+    fn exhausted_at(&self, offset: usize) -> bool {
+        self.max_count.is_some_and(|max_count| self.match_count >= max_count)
+            || self.end.is_some_and(|end| offset >= end)
+    }
+
+    pub fn limit_reached(&self) -> bool {
+        self.exhausted_at(self.consumed)
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.patterns.max_len()
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.match_count
+    }
+
+    // turns recording on or off, turning it off drops matches recorded so far.
+    pub fn record_matches(&mut self, enable: bool) {
+        if !enable {
+            self.recorded = None;
+        } else if self.recorded.is_none() {
+            self.recorded = Some(Vec::new());
+        }
+    }
+
+    pub fn matches(&self) -> &[Match] {
+        self.recorded.as_deref().unwrap_or(&[])
+    }
+
+    pub fn take_matches(&mut self) -> Vec<Match> {
+        self.recorded.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // rewrites as much of input as can be decided into output, and returns how many bytes of input were consumed.
+    //
+    // The unconsumed tail is always shorter than max_len() and must be presented again,
+    // followed by more data, on the next call. When eof is true the whole input is consumed.
+    pub fn process(&mut self, input: &[u8], eof: bool, output: &mut Vec<u8>) -> usize {
+        let output_base = output.len();
+        let mut pos = 0;
+        let mut copied = 0;
+        while pos < input.len() {
+            let offset = self.consumed + pos;
+            if self.exhausted_at(offset) {
+                pos = input.len();
+                break;
+            }
+            if offset < self.start {
+                pos = input.len().min(self.start - self.consumed);
+                continue;
+            }
+            match self.patterns.probe(&input[pos..], eof) {
+                Probe::Match { index, len } if self.end.map_or(true, |end| offset + len <= end) => {
+                    output.extend_from_slice(&input[copied..pos]);
+                    let replacement_start = output.len();
+                    match self.replacements {
+                        Replacements::Fixed(ref fixed) => output.extend_from_slice(&fixed[index]),
+                        Replacements::With(ref mut f) => f(self.match_count, offset, output),
+                    }
+                    if let Some(ref mut recorded) = self.recorded {
+                        recorded.push(Match {
+                            pattern: index,
+                            input_offset: offset,
+                            input_len: len,
+                            output_offset: self.produced + replacement_start - output_base,
+                            output_len: output.len() - replacement_start,
+                        });
+                    }
+                    self.match_count += 1;
+                    pos += len;
+                    copied = pos;
+                }
+                Probe::Match { .. } | Probe::NoMatch => pos += 1,
+                Probe::Incomplete => break,
+            }
+        }
+        output.extend_from_slice(&input[copied..pos]);
+        self.consumed += pos;
+        self.produced += output.len() - output_base;
+        pos
+    }
+}
@@ -1,312 +1,573 @@
-use crate::read_full;
-use std::io::{self, Read};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::{Bound, RangeBounds};
 
-enum ReplacingReaderState {
-    // the buffer has not been initialized yet
-    NotInitialized,
+mod matcher;
+#[cfg(feature = "regex")]
+mod regex_replace;
 
-    // the buffer is in this sequence: [4 5 6 7 0 1 2 3]
-    LastReadIsMiddle,
+use matcher::{PatternSet, Replacements, Replacer};
+#[cfg(feature = "regex")]
+pub use regex_replace::RegexReplacingReader;
 
-    // the buffer is in this sequence: [0 1 2 3 4 5 6 7]
-    LastReadIsStart,
+/// Match describes where a replacement happened.
+///
+/// Offsets count bytes from the start of the stream, the input side being the original stream
+/// and the output side the rewritten one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    /// Index of the pattern that matched, always 0 for single pattern replacement.
+    pub pattern: usize,
+    pub input_offset: usize,
+    pub input_len: usize,
+    pub output_offset: usize,
+    pub output_len: usize,
+}
+
+/// PatternError is returned when a replacing adapter is given a pattern it can not work with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatternError {
+    /// The pattern at this index is empty, which would match everywhere.
+    EmptyPattern(usize),
+    /// The pattern at this index is not valid UTF-8, which Unicode case insensitive matching requires.
+    InvalidUtf8(usize),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::EmptyPattern(index) => write!(f, "pattern {} can not be empty", index),
+            PatternError::InvalidUtf8(index) => write!(f, "pattern {} is not valid UTF-8", index),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// CaseMatching selects how letter case is treated when matching a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseMatching {
+    /// Bytes must be equal, this is the default.
+    #[default]
+    Sensitive,
+    /// ASCII letters match regardless of case, other bytes must be equal.
+    AsciiInsensitive,
+    /// Characters match if they are the same after simple case folding, e.g. "straße" matches "STRAẞE"
+    /// but not "STRASSE", as folding never changes the number of characters.
+    ///
+    /// The pattern must be valid UTF-8, invalid UTF-8 in the stream never matches.
+    /// A match may differ in length from the pattern, the buffer is sized for 4 * (characters in pattern).
+    UnicodeInsensitive,
+}
+
+// ReplacingCore is the buffering shared by the replacing readers: it pulls raw bytes from the underlying reader,
+// runs them through a Replacer and serves the rewritten bytes.
+struct ReplacingCore<'a> {
+    replacer: Replacer<'a>,
+
+    // bytes read from the underlying reader which are not yet decided on, at most buffer_size long
+    pending: Vec<u8>,
+    buffer_size: usize,
+
+    // rewritten bytes waiting to be served, starting at output_ptr
+    output: Vec<u8>,
+    output_ptr: usize,
+
+    eof: bool,
+}
+
+impl<'a> ReplacingCore<'a> {
+    // buffer_size is raised to the longest pattern if needed, which is the least that guarantees progress
+    fn new(replacer: Replacer<'a>, buffer_size: Option<usize>) -> ReplacingCore<'a> {
+        let min_size = replacer.max_len().max(1);
+        let buffer_size = buffer_size.unwrap_or(2 * min_size).max(min_size);
+        ReplacingCore {
+            replacer,
+            pending: Vec::with_capacity(buffer_size),
+            buffer_size,
+            output: Vec::new(),
+            output_ptr: 0,
+            eof: false,
+        }
+    }
+
+    fn fill_buf<R: Read>(&mut self, r: &mut R) -> Result<&[u8], io::Error> {
+        while self.output_ptr == self.output.len() && !(self.eof && self.pending.is_empty()) {
+            self.output.clear();
+            self.output_ptr = 0;
+            if !self.eof {
+                let filled = self.pending.len();
+                self.pending.resize(self.buffer_size, 0);
+                // pending is never full here, as process() consumes all but max_len - 1 bytes at most,
+                // so reading 0 bytes means end of stream
+                let size = match r.read(&mut self.pending[filled..]) {
+                    Ok(size) => {
+                        self.eof = size == 0;
+                        size
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                    Err(e) => {
+                        self.pending.truncate(filled);
+                        return Err(e);
+                    }
+                };
+                self.pending.truncate(filled + size);
+            }
+            let consumed = self.replacer.process(&self.pending, self.eof, &mut self.output);
+            self.pending.drain(..consumed);
+        }
+        Ok(&self.output[self.output_ptr..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.output_ptr = (self.output_ptr + amt).min(self.output.len());
+    }
+
+    fn read<R: Read>(&mut self, r: &mut R, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let available = self.fill_buf(r)?;
+        let size = available.len().min(buf.len());
+        buf[..size].copy_from_slice(&available[..size]);
+        self.consume(size);
+        Ok(size)
+    }
 }
 
 /// ReplacingReader wraps around an underlying reader and transiently replaces given patterns in the read.
 ///
-/// The pattern must no overlap, in such case the behavior is undefined.
-/// The internal buffer is 2 * len(old_pattern), caller can wrap std::io::BufReader if more buffer is required.
+/// Occurrences are replaced from left to right and never overlap: once an occurrence is replaced,
+/// matching resumes right after it. So replacing "aa" in "aaa" leaves the last "a" alone,
+/// the same as str::replace would.
+/// ReplacingReader implements BufRead on its own, serving the rewritten bytes from its internal buffer.
+/// The internal buffer is 2 * len(old_pattern) by default, use builder() to set a larger one.
 ///
-/// A runtime panic will be thrown if old.len() == 0.
-pub struct ReplacingReader<'a> {
-    underlying_reader: &'a mut dyn Read,
-    // buffer is separated into two parts and has a capacity of 2 * old_pattern.len()
-    //
-    // buffer:         X X X A | B C X X
-    // next_match_ptr:       *
-    // read_ptr:       *
-    // next time when read_ptr is about to hit next_match_ptr, we transition to feed new to read() call
-    buffer: Vec<u8>,
-    old_pattern: &'a [u8],
-    new_pattern: &'a [u8],
-    read_ptr: usize,
-
-    state: ReplacingReaderState,
-
-    // this is the location of eof in the buffer, if already met
-    // the last byte should be buffer[eof_position - 1]
-    eof_position: Option<usize>,
-
-    // this is the location of the next match, if present
-    next_match_ptr: Option<usize>,
-
-    // if this is Some, we are in progress of serving from new_pattern,
-    // this should be set to None when serve_new_ptr == Some(new_pattern.size())
-    serve_new_ptr: Option<usize>,
+/// new() and new_with() panic if old.len() == 0, use try_new() or builder() to get an error instead.
+pub struct ReplacingReader<'a, R> {
+    underlying_reader: R,
+    core: ReplacingCore<'a>,
 }
 
-impl ReplacingReader<'_> {
-    pub fn new<'a>(r: &'a mut dyn Read, old: &'a [u8], new: &'a [u8]) -> ReplacingReader<'a> {
-        if old.len() ==  0 { panic!("old pattern can not be empty") };
+/// ReplacingReaderBuilder configures a ReplacingReader, it is obtained from ReplacingReader::builder().
+pub struct ReplacingReaderBuilder<'a> {
+    old: Vec<u8>,
+    replacements: Replacements<'a>,
+    buffer_size: Option<usize>,
+    case: CaseMatching,
+    max_replacements: Option<usize>,
+    range: (usize, Option<usize>),
+}
+
+impl<'a> ReplacingReaderBuilder<'a> {
+    /// Replaces each occurrence with `new`, this is the default with an empty `new`.
+    pub fn replacement(mut self, new: &[u8]) -> ReplacingReaderBuilder<'a> {
+        self.replacements = Replacements::Fixed(vec![new.to_vec()]);
+        self
+    }
+
+    /// Replaces each occurrence with whatever `f` returns for it, see ReplacingReader::new_with().
+    pub fn replace_with<F, T>(mut self, mut f: F) -> ReplacingReaderBuilder<'a>
+    where
+        F: FnMut(usize, usize) -> T + Send + 'a,
+        T: AsRef<[u8]>,
+    {
+        self.replacements = Replacements::With(Box::new(move |index, offset, output: &mut Vec<u8>| {
+            output.extend_from_slice(f(index, offset).as_ref())
+        }));
+        self
+    }
+
+    /// Sets how letter case is treated when matching `old`, which defaults to CaseMatching::Sensitive.
+    pub fn case_matching(mut self, case: CaseMatching) -> ReplacingReaderBuilder<'a> {
+        self.case = case;
+        self
+    }
+
+    /// Sets the size of the internal buffer, which defaults to 2 * len(old),
+    /// or to 8 * (characters in old) with CaseMatching::UnicodeInsensitive.
+    ///
+    /// A size smaller than len(old) is raised to len(old),
+    /// or to 4 * (characters in old) with CaseMatching::UnicodeInsensitive.
+    pub fn buffer_size(mut self, size: usize) -> ReplacingReaderBuilder<'a> {
+        self.buffer_size = Some(size);
+        self
+    }
+
+    /// Replaces only the first `count` occurrences, the rest of the stream is passed through unchanged.
+    pub fn max_replacements(mut self, count: usize) -> ReplacingReaderBuilder<'a> {
+        self.max_replacements = Some(count);
+        self
+    }
+
+    /// Replaces only occurrences lying entirely within `range`, given as offsets in the underlying stream.
+    ///
+    /// For example `.range(512..)` leaves the first 512 bytes alone, and `.range(..512)` only patches them.
+    pub fn range<T: RangeBounds<usize>>(mut self, range: T) -> ReplacingReaderBuilder<'a> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => Some(start),
+            Bound::Excluded(&start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        // an end past usize::MAX is as good as no end
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1),
+            Bound::Excluded(&end) => Some(end),
+            Bound::Unbounded => None,
+        };
+        self.range = match start {
+            Some(start) => (start, end),
+            // nothing starts after usize::MAX, the range is empty
+            None => (0, Some(0)),
+        };
+        self
+    }
 
-        let buffer = vec![0; 2 * old.len()];
-        ReplacingReader {
+    /// Creates the reader on top of `r`.
+    pub fn build<R: Read>(self, r: R) -> Result<ReplacingReader<'a, R>, PatternError> {
+        let patterns = PatternSet::new(Some(&self.old), self.case)?;
+        let (start, end) = self.range;
+        let replacer =
+            Replacer::with_replacements(patterns, self.replacements).with_limits(self.max_replacements, start, end);
+        Ok(ReplacingReader {
             underlying_reader: r,
-            old_pattern: old,
-            new_pattern: new,
-            read_ptr: 0,
-            buffer: buffer,
-            state: ReplacingReaderState::NotInitialized,
-            eof_position: None,
+            core: ReplacingCore::new(replacer, self.buffer_size),
+        })
+    }
+}
 
-            next_match_ptr: None,
-            serve_new_ptr: None,
+impl ReplacingReader<'_, ()> {
+    /// Starts configuring a reader replacing `old`.
+    pub fn builder<'a>(old: &[u8]) -> ReplacingReaderBuilder<'a> {
+        ReplacingReaderBuilder {
+            old: old.to_vec(),
+            replacements: Replacements::Fixed(vec![Vec::new()]),
+            buffer_size: None,
+            case: CaseMatching::default(),
+            max_replacements: None,
+            range: (0, None),
         }
     }
+}
 
-    #[inline(always)]
-    fn try_match_from(&self, start: usize) -> bool {
-        let mut ptr = start;
-        let mut match_len = 0usize;
-        loop {
-            if match_len == self.old_pattern.len() {
-                return true;
-            }
-            if self.buffer[ptr] == self.old_pattern[match_len] {
-                match_len += 1;
-                ptr += 1;
-                if ptr == self.buffer.len() {
-                    ptr = 0;
-                }
-            } else {
-                return false;
-            }
-        }
+impl<'a, R: Read> ReplacingReader<'a, R> {
+    pub fn new(r: R, old: &[u8], new: &[u8]) -> ReplacingReader<'a, R> {
+        ReplacingReader::try_new(r, old, new).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as new(), but returns an error instead of panicking.
+    pub fn try_new(r: R, old: &[u8], new: &[u8]) -> Result<ReplacingReader<'a, R>, PatternError> {
+        ReplacingReader::builder(old).replacement(new).build(r)
+    }
+
+    /// Creates a reader replacing each occurrence of `old` with whatever `f` returns for it.
+    ///
+    /// `f` is called with the number of the occurrence, counting from 0, and its offset in the underlying stream.
+    /// It may borrow local state, the reader is then bound to the lifetime `'a` of the borrow.
+    pub fn new_with<F, T>(r: R, old: &[u8], f: F) -> ReplacingReader<'a, R>
+    where
+        F: FnMut(usize, usize) -> T + Send + 'a,
+        T: AsRef<[u8]>,
+    {
+        ReplacingReader::builder(old).replace_with(f).build(r).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the number of replacements made so far.
+    pub fn replacement_count(&self) -> usize {
+        self.core.replacer.match_count()
+    }
+
+    /// Returns true once the limits set with max_replacements() or range() rule out any further replacement,
+    /// either because enough occurrences were replaced or because the stream went past the end of the range.
+    pub fn limit_reached(&self) -> bool {
+        self.core.replacer.limit_reached()
+    }
+
+    /// Turns recording of matches on or off, turning it off drops the matches recorded so far.
+    ///
+    /// A match is recorded as soon as it is found, which may be before its replacement is read out.
+    pub fn record_matches(&mut self, enable: bool) {
+        self.core.replacer.record_matches(enable)
+    }
+
+    /// Returns the matches recorded and not yet taken.
+    pub fn matches(&self) -> &[Match] {
+        self.core.replacer.matches()
+    }
+
+    /// Takes the matches recorded so far, so that a long running stream does not accumulate them.
+    pub fn take_matches(&mut self) -> Vec<Match> {
+        self.core.replacer.take_matches()
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.underlying_reader
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading directly from it skips over the data, and may corrupt the replacement.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.underlying_reader
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    ///
+    /// Data already read from the underlying reader and not yet served is lost.
+    pub fn into_inner(self) -> R {
+        self.underlying_reader
     }
 }
 
-impl Read for ReplacingReader<'_> {
+impl<R: Read> Read for ReplacingReader<'_, R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        let buf_available = buf.len();
-        // first check if we are already serving new_pattern
-        if let Some(new_ptr) = self.serve_new_ptr {
-            let remaining_new_pattern_len = self.new_pattern.len() - new_ptr;
-            if remaining_new_pattern_len > buf_available {
-                buf.copy_from_slice(&self.new_pattern[new_ptr..new_ptr + buf_available]);
-                self.serve_new_ptr = Some(new_ptr + buf_available);
-                return Ok(buf_available);
-            } else if remaining_new_pattern_len > 0 {
-                buf[..remaining_new_pattern_len].copy_from_slice(&self.new_pattern[new_ptr..]);
-                self.serve_new_ptr = None;
-                return Ok(remaining_new_pattern_len);
-            }
-        }
+        self.core.read(&mut self.underlying_reader, buf)
+    }
+}
 
-        // then, if this read is going to enter self.next_match_ptr?
-        if let Some(next_match_ptr) = self.next_match_ptr {
-            if next_match_ptr > self.read_ptr {
-                let remaining_buf_available = next_match_ptr - self.read_ptr;
-                if buf_available >= remaining_buf_available {
-                    // we can read until start of match
-                    buf[..remaining_buf_available]
-                        .copy_from_slice(&self.buffer[self.read_ptr..next_match_ptr]);
-                    self.serve_new_ptr = Some(0);
-                    self.read_ptr = next_match_ptr + self.old_pattern.len();
-                    if self.read_ptr >= self.buffer.len() {
-                        self.read_ptr -= self.buffer.len();
-                    }
-                    self.next_match_ptr = None;
-                    return Ok(remaining_buf_available);
-                } else {
-                    buf.copy_from_slice(&self.buffer[self.read_ptr..self.read_ptr + buf_available]);
-                    self.read_ptr += buf_available;
-                    return Ok(buf_available);
-                }
-            } else if next_match_ptr == self.read_ptr {
-                self.serve_new_ptr = Some(0);
-                self.read_ptr += self.old_pattern.len() ;
-                if self.read_ptr >= self.buffer.len() {
-                    self.read_ptr -= self.buffer.len();
-                }
-                self.next_match_ptr = None;
-                return self.read(buf);
-            } {
-                let remaining_buf_available = self.buffer.len() - self.read_ptr;
-                if buf_available >= remaining_buf_available {
-                    buf[..remaining_buf_available].copy_from_slice(&self.buffer[self.read_ptr..]);
-                    self.read_ptr = 0;
-                    return Ok(remaining_buf_available);
-                } else {
-                    buf.copy_from_slice(&self.buffer[self.read_ptr..self.read_ptr + buf_available]);
-                    self.read_ptr += buf_available;
-                    return Ok(buf_available);
-                }
-            }
-        }
+impl<R: Read> BufRead for ReplacingReader<'_, R> {
+    fn fill_buf(&mut self) -> Result<&[u8], io::Error> {
+        self.core.fill_buf(&mut self.underlying_reader)
+    }
 
-        // initialize the buffer first
-        match self.state {
-            ReplacingReaderState::NotInitialized => {
-                // first we make a full read to fill the buffer
-                match read_full(&mut self.buffer, self.underlying_reader) {
-                    Ok(read_len) => {
-                        if read_len < self.buffer.len() {
-                            // we already hit eof
-                            self.eof_position = Some(read_len);
-                        }
-                        if read_len >= self.old_pattern.len() {
-                            let possible_match_start = read_len - self.old_pattern.len();
-                            for guess_start in 0..possible_match_start {
-                                if self.try_match_from(guess_start) {
-                                    self.next_match_ptr = Some(guess_start);
-                                    break;
-                                }
-                            }
-                        }
+    fn consume(&mut self, amt: usize) {
+        self.core.consume(amt)
+    }
+}
 
-                        self.state = ReplacingReaderState::LastReadIsMiddle;
-                        return self.read(buf);
-                    }
-                    Err(e) => return Err(e),
-                };
-            }
-            _ => (),
-        };
+/// MultiReplacingReader wraps around an underlying reader and replaces several patterns in a single pass.
+///
+/// Patterns are matched leftmost-longest: at the earliest position where any pattern matches,
+/// the longest matching pattern is replaced, and scanning resumes right after it.
+/// So with patterns "ab", "abcd" and "bc", the input "abcde" becomes new("abcd") + "e".
+/// If the same pattern is given twice, the first replacement is used.
+///
+/// MultiReplacingReader implements BufRead on its own, its internal buffer is 2 * len(longest pattern).
+///
+/// new() panics if any pattern is empty, use try_new() to get an error instead.
+pub struct MultiReplacingReader<R> {
+    underlying_reader: R,
+    core: ReplacingCore<'static>,
+}
 
-        // if we are at the end of stream and no patterns were found, nothing to do except serve the last bit of stream until end.
-        if let Some(eof_position) = self.eof_position {
-            // remaining buffer is from read_ptr to eof_position
-            if eof_position < self.read_ptr {
-                // read at most into the end of buffer
-                let max_read_size = self.buffer.len() - self.read_ptr;
-                if max_read_size >= self.old_pattern.len() {
-                    for guess_start in self.read_ptr..self.read_ptr + 1 + max_read_size - self.old_pattern.len() {
-                        if self.try_match_from(guess_start) {
-                            self.next_match_ptr = Some(guess_start % self.buffer.len());
-                            return self.read(buf);
-                        }
-                    }
-                }
-                if max_read_size > buf_available {
-                    buf.copy_from_slice(&self.buffer[self.read_ptr..self.read_ptr + buf_available]);
-                    self.read_ptr += buf_available;
-                    return Ok(buf_available);
-                } else {
-                    buf[..max_read_size].copy_from_slice(&self.buffer[self.read_ptr..]);
-                    self.read_ptr = 0;
-                    return Ok(max_read_size);
-                }
-            } else if eof_position == self.read_ptr {
-                return Ok(0);
-            } else {
-                let max_read_size = eof_position - self.read_ptr;
-                if max_read_size >= self.old_pattern.len() {
-                    for guess_start in self.read_ptr..self.read_ptr + 1 + max_read_size - self.old_pattern.len() {
-                        if self.try_match_from(guess_start) {
-                            self.next_match_ptr = Some(guess_start);
-                            return self.read(buf);
-                        }
-                    }
-                }
-                if max_read_size > buf_available {
-                    buf.copy_from_slice(&self.buffer[self.read_ptr..self.read_ptr + buf_available]);
-                    self.read_ptr += buf_available;
-                    return Ok(buf_available);
-                } else {
-                    buf[..max_read_size].copy_from_slice(&self.buffer[self.read_ptr..eof_position]);
-                    self.read_ptr += max_read_size;
-                    return Ok(max_read_size);
-                }
-            }
-        }
+impl<R: Read> MultiReplacingReader<R> {
+    /// Creates a reader replacing each `old` with its `new` in the given pairs.
+    pub fn new<I, P, N>(r: R, pairs: I) -> MultiReplacingReader<R>
+    where
+        I: IntoIterator<Item = (P, N)>,
+        P: AsRef<[u8]>,
+        N: AsRef<[u8]>,
+    {
+        MultiReplacingReader::try_new(r, pairs).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        // here is the general case: either serve until the older half of buffer was empty or we advance buffer and do the actual pattern matching
-        let wrap_pos = self.old_pattern.len();
-        match self.state {
-            ReplacingReaderState::LastReadIsStart => {
-                if self.read_ptr >= wrap_pos {
-                    let remaining_data_len = self.buffer.len() - self.read_ptr;
-                    if buf_available >= remaining_data_len {
-                        buf[..remaining_data_len].copy_from_slice(&self.buffer[self.read_ptr..]);
-                        self.read_ptr = 0;
-                        return Ok(remaining_data_len);
-                    } else {
-                        buf.copy_from_slice(
-                            &self.buffer[self.read_ptr..self.read_ptr + buf_available],
-                        );
-                        self.read_ptr += buf_available;
-                        return Ok(buf_available);
-                    }
-                }
-                // next we read from the middle
-                match read_full(&mut self.buffer[wrap_pos..], self.underlying_reader) {
-                    Ok(size) => {
-                        let mut last_possible_match_start = wrap_pos;
-                        if size < self.old_pattern.len() {
-                            // eof is met, set eof position
-                            let eof_position = wrap_pos + size;
-                            last_possible_match_start = eof_position - self.old_pattern.len()  ;
-                            self.eof_position = Some(eof_position);
-                        }
-                        let first_possible_match_start = if self.read_ptr<1 {0} else {self.read_ptr};
-                        for guess_start in first_possible_match_start..last_possible_match_start {
-                            if self.try_match_from(guess_start) {
-                                self.next_match_ptr = Some(guess_start);
-                            }
-                        }
-                    }
-                    Err(e) => return Err(e),
+    /// Same as new(), but returns an error instead of panicking.
+    pub fn try_new<I, P, N>(r: R, pairs: I) -> Result<MultiReplacingReader<R>, PatternError>
+    where
+        I: IntoIterator<Item = (P, N)>,
+        P: AsRef<[u8]>,
+        N: AsRef<[u8]>,
+    {
+        Ok(MultiReplacingReader {
+            underlying_reader: r,
+            core: ReplacingCore::new(Replacer::new(pairs)?, None),
+        })
+    }
 
-                };
-                self.state = ReplacingReaderState::LastReadIsMiddle;
+    /// Returns the number of replacements made so far.
+    pub fn replacement_count(&self) -> usize {
+        self.core.replacer.match_count()
+    }
+
+    /// Turns recording of matches on or off, turning it off drops the matches recorded so far.
+    ///
+    /// A match is recorded as soon as it is found, which may be before its replacement is read out.
+    pub fn record_matches(&mut self, enable: bool) {
+        self.core.replacer.record_matches(enable)
+    }
+
+    /// Returns the matches recorded and not yet taken.
+    pub fn matches(&self) -> &[Match] {
+        self.core.replacer.matches()
+    }
+
+    /// Takes the matches recorded so far, so that a long running stream does not accumulate them.
+    pub fn take_matches(&mut self) -> Vec<Match> {
+        self.core.replacer.take_matches()
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.underlying_reader
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading directly from it skips over the data, and may corrupt the replacement.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.underlying_reader
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    ///
+    /// Data already read from the underlying reader and not yet served is lost.
+    pub fn into_inner(self) -> R {
+        self.underlying_reader
+    }
+}
+
+impl<R: Read> Read for MultiReplacingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        self.core.read(&mut self.underlying_reader, buf)
+    }
+}
+
+impl<R: Read> BufRead for MultiReplacingReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8], io::Error> {
+        self.core.fill_buf(&mut self.underlying_reader)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.core.consume(amt)
+    }
+}
+
+/// ReplacingWriter wraps around an underlying writer and transiently replaces given patterns in the data written to it.
+///
+/// Matches are found across arbitrary write boundaries: the last len(old_pattern) - 1 bytes at most are held back
+/// until it is known whether they start a match. flush() gives up on a held back partial match and
+/// writes it out unchanged, so a match split by a flush() is not replaced. Dropping the writer flushes it,
+/// ignoring any error.
+///
+/// new() panics if old.len() == 0, use try_new() to get an error instead.
+pub struct ReplacingWriter<W: Write> {
+    // only None once into_inner() took it
+    underlying_writer: Option<W>,
+    replacer: Replacer<'static>,
+
+    // written bytes which may still be the start of a match
+    pending: Vec<u8>,
+
+    // rewritten bytes not yet accepted by underlying_writer
+    output: Vec<u8>,
+
+    panicked: bool,
+}
+
+impl<W: Write> ReplacingWriter<W> {
+    pub fn new(w: W, old: &[u8], new: &[u8]) -> ReplacingWriter<W> {
+        ReplacingWriter::try_new(w, old, new).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as new(), but returns an error instead of panicking.
+    pub fn try_new(w: W, old: &[u8], new: &[u8]) -> Result<ReplacingWriter<W>, PatternError> {
+        Ok(ReplacingWriter {
+            underlying_writer: Some(w),
+            replacer: Replacer::new(Some((old, new)))?,
+            pending: Vec::with_capacity(old.len()),
+            output: Vec::new(),
+            panicked: false,
+        })
+    }
+
+    /// Returns the number of replacements made so far.
+    pub fn replacement_count(&self) -> usize {
+        self.replacer.match_count()
+    }
+
+    /// Turns recording of matches on or off, turning it off drops the matches recorded so far.
+    ///
+    /// A match is recorded as soon as it is found, which may be before its replacement is passed to the underlying writer.
+    pub fn record_matches(&mut self, enable: bool) {
+        self.replacer.record_matches(enable)
+    }
+
+    /// Returns the matches recorded and not yet taken.
+    pub fn matches(&self) -> &[Match] {
+        self.replacer.matches()
+    }
+
+    /// Takes the matches recorded so far, so that a long running stream does not accumulate them.
+    pub fn take_matches(&mut self) -> Vec<Match> {
+        self.replacer.take_matches()
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.underlying_writer.as_ref().unwrap()
+    }
+
+    /// Gets a mutable reference to the underlying writer.
+    ///
+    /// Writing directly to it puts the data ahead of anything still held back.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.underlying_writer.as_mut().unwrap()
+    }
+
+    /// Flushes this writer, including any held back partial match, and returns the underlying writer.
+    ///
+    /// If flushing fails, the error is returned and the underlying writer is dropped.
+    pub fn into_inner(mut self) -> Result<W, io::Error> {
+        self.flush()?;
+        Ok(self.underlying_writer.take().unwrap())
+    }
+
+    // writes out as much of output as underlying_writer accepts.
+    fn write_output(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.output.len() {
+                break Ok(());
             }
-            ReplacingReaderState::LastReadIsMiddle => {
-                if self.read_ptr < wrap_pos {
-                    // we still need to serve up to wrap_pos
-                    let remaining_data_len = wrap_pos - self.read_ptr;
-                    if buf_available >= remaining_data_len {
-                        buf[..remaining_data_len]
-                            .copy_from_slice(&self.buffer[self.read_ptr..wrap_pos]);
-                        self.read_ptr = wrap_pos;
-                        return Ok(remaining_data_len);
-                    } else {
-                        buf.copy_from_slice(
-                            &self.buffer[self.read_ptr..self.read_ptr + buf_available],
-                        );
-                        self.read_ptr += buf_available;
-                        return Ok(buf_available);
-                    }
-                }
-                match read_full(&mut self.buffer[..wrap_pos], self.underlying_reader) {
-                    Ok(size) => {
-                        let first_possible_match_start =  if self.read_ptr > wrap_pos {self.read_ptr} else {wrap_pos };
-                        let mut last_possible_match_start = self.buffer.len();
-                        if size < self.old_pattern.len() {
-                            let eof_position = size;
-                            last_possible_match_start =
-                                self.buffer.len() - self.old_pattern.len() + size;
-                            self.eof_position = Some(eof_position);
-                        }
-                        for guess_start in first_possible_match_start..last_possible_match_start {
-                            if self.try_match_from(guess_start % self.buffer.len()) {
-                                self.next_match_ptr = Some(guess_start % self.buffer.len());
-                            }
-                        }
-                    }
-                    Err(e) => return Err(e),
-                }
-                self.state = ReplacingReaderState::LastReadIsStart;
+            self.panicked = true;
+            let r = self.underlying_writer.as_mut().unwrap().write(&self.output[written..]);
+            self.panicked = false;
+            match r {
+                Ok(0) => break Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write replaced data")),
+                Ok(size) => written += size,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => break Err(e),
             }
-            _ => panic!("unknown state"),
-        }
+        };
+        self.output.drain(..written);
+        result
+    }
+}
+
+impl<W: Write> Write for ReplacingWriter<W> {
+    // An error from the underlying writer is returned before buf is accepted. Once buf is accepted
+    // Ok(buf.len()) is returned, and failing to pass it on is reported by the next write() or flush().
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_output()?;
+
+        self.pending.extend_from_slice(buf);
+        let consumed = self.replacer.process(&self.pending, false, &mut self.output);
+        self.pending.drain(..consumed);
+
+        let _ = self.write_output();
+        Ok(buf.len())
+    }
 
-        return self.read(buf);
+    fn flush(&mut self) -> io::Result<()> {
+        self.replacer.process(&self.pending, true, &mut self.output);
+        self.pending.clear();
+        self.write_output()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for ReplacingWriter<W> {
+    fn drop(&mut self) {
+        // do not write into a writer which panicked mid-write
+        if self.underlying_writer.is_some() && !self.panicked {
+            let _ = self.flush();
+        }
     }
 }
 
 #[cfg(test)]
 mod testconv {
 
+    #[allow(clippy::iter_nth_zero)]
     mod test_replacing_reader {
         use crate::conv::ReplacingReader;
         use std::io::Read;
@@ -403,4 +664,657 @@ mod testconv {
 
         }
     }
+
+    mod test_replacing_reader_with {
+        use crate::conv::ReplacingReader;
+        use std::io::Read;
+
+        #[test]
+        fn test_numbered() {
+            let mut input = "a?b?c?".as_bytes();
+            let mut reader = ReplacingReader::new_with(&mut input, b"?", |index, _offset| (index + 1).to_string());
+            let mut ret = String::new();
+            reader.read_to_string(&mut ret).unwrap();
+            assert_eq!(ret, "a1b2c3");
+        }
+
+        #[test]
+        fn test_offsets() {
+            let input = "<>ab<><>cdefg<>".repeat(3);
+            let mut input_bytes = input.as_bytes();
+            let mut offsets = Vec::new();
+            let mut ret = String::new();
+            {
+                let mut reader = ReplacingReader::new_with(&mut input_bytes, b"<>", |index, offset| {
+                    offsets.push(offset);
+                    if index % 2 == 0 { "even" } else { "" }
+                });
+                reader.read_to_string(&mut ret).unwrap();
+            }
+            assert_eq!(offsets, [0, 4, 6, 13, 15, 19, 21, 28, 30, 34, 36, 43]);
+            assert_eq!(ret, "evenabevencdefgevenabevencdefgevenabevencdefg");
+        }
+    }
+
+    mod test_multi_replacing_reader {
+        use crate::conv::MultiReplacingReader;
+        use std::io::Read;
+
+        fn run_string_through(input: &str, pairs: &[(&str, &str)]) -> String {
+            let mut input_bytes = input.as_bytes();
+            let mut reader = MultiReplacingReader::new(&mut input_bytes, pairs.iter().copied());
+            let mut ret = String::new();
+            reader.read_to_string(&mut ret).unwrap();
+            ret
+        }
+
+        // leftmost-longest replacement done the slow way, on the whole input at once
+        fn replace_naive(input: &str, pairs: &[(&str, &str)]) -> String {
+            let mut ret = String::new();
+            let mut pos = 0;
+            while pos < input.len() {
+                let longest = pairs
+                    .iter()
+                    .filter(|(old, _)| input[pos..].starts_with(old))
+                    .fold(None, |best: Option<&(&str, &str)>, pair| match best {
+                        Some(best) if best.0.len() >= pair.0.len() => Some(best),
+                        _ => Some(pair),
+                    });
+                match longest {
+                    Some((old, new)) => {
+                        ret.push_str(new);
+                        pos += old.len();
+                    }
+                    None => {
+                        ret.push_str(&input[pos..pos + 1]);
+                        pos += 1;
+                    }
+                }
+            }
+            ret
+        }
+
+        #[test]
+        fn test_simple() {
+            let pairs = [("cat", "dog"), ("red", "blue")];
+            assert_eq!(
+                run_string_through("a red cat and a cat in red", &pairs),
+                "a blue dog and a dog in blue"
+            );
+        }
+
+        #[test]
+        fn test_leftmost_longest() {
+            let pairs = [("ab", "1"), ("abcd", "2"), ("bc", "3")];
+            assert_eq!(run_string_through("abcde", &pairs), "2e");
+            assert_eq!(run_string_through("abcabcd", &pairs), "1c2");
+            assert_eq!(run_string_through("xbcd", &pairs), "x3d");
+        }
+
+        #[test]
+        fn test_longer_prefix_at_eof() {
+            let pairs = [("a", "1"), ("aaaa", "2")];
+            assert_eq!(run_string_through("aaa", &pairs), "111");
+            assert_eq!(run_string_through("aaaaa", &pairs), "21");
+        }
+
+        #[test]
+        fn test_duplicate_pattern_first_wins() {
+            let pairs = [("ab", "1"), ("ab", "2")];
+            assert_eq!(run_string_through("abab", &pairs), "11");
+        }
+
+        #[test]
+        fn test_no_pattern() {
+            assert_eq!(run_string_through("abcdef", &[]), "abcdef");
+        }
+
+        #[test]
+        fn test_against_naive() {
+            let pairs = [("a", "<A>"), ("aba", ""), ("bab", "<BAB>"), ("abaab", "<X>"), ("c", "cc")];
+            // every string over "abc" up to 7 characters long
+            for len in 0..=7u32 {
+                for mut n in 0..3usize.pow(len) {
+                    let mut input = String::new();
+                    for _ in 0..len {
+                        input.push(['a', 'b', 'c'][n % 3]);
+                        n /= 3;
+                    }
+                    assert_eq!(run_string_through(&input, &pairs), replace_naive(&input, &pairs), "input {:?}", input);
+                }
+            }
+        }
+
+        #[test]
+        fn test_small_reads() {
+            let input = "the quick brown fox jumps over the lazy dog".repeat(10);
+            let pairs = [("the", "a"), ("quick", "slow"), ("o", "0"), ("over", "under")];
+            let mut input_bytes = input.as_bytes();
+            let mut reader = MultiReplacingReader::new(&mut input_bytes, pairs.iter().copied());
+            let mut ret = Vec::new();
+            let mut buf = [0u8; 3];
+            loop {
+                let size = reader.read(&mut buf).unwrap();
+                if size == 0 {
+                    break;
+                }
+                ret.extend_from_slice(&buf[..size]);
+            }
+            assert_eq!(String::from_utf8(ret).unwrap(), replace_naive(&input, &pairs));
+        }
+    }
+
+    mod test_replacing_writer {
+        use crate::conv::ReplacingWriter;
+        use crate::tests::TestWriter;
+        use std::io::{self, Write};
+
+        fn write_in_chunks(input: &str, old: &str, new: &str, chunk_len: usize) -> String {
+            let mut output = Vec::new();
+            {
+                let mut writer = ReplacingWriter::new(&mut output, old.as_bytes(), new.as_bytes());
+                for chunk in input.as_bytes().chunks(chunk_len) {
+                    writer.write_all(chunk).unwrap();
+                }
+                writer.flush().unwrap();
+            }
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn test_across_write_boundaries() {
+            let input = "abcabcabcabcabc";
+            for chunk_len in 1..=input.len() {
+                assert_eq!(write_in_chunks(input, "ab", "cde", chunk_len), "cdeccdeccdeccdeccdec");
+                assert_eq!(write_in_chunks(input, "cabca", "_", chunk_len), "ab_b_bc");
+            }
+        }
+
+        #[test]
+        fn test_flush_gives_up_partial_match() {
+            let mut output = Vec::new();
+            {
+                let mut writer = ReplacingWriter::new(&mut output, b"abc", b"X");
+                writer.write_all(b"0ab").unwrap();
+                writer.flush().unwrap();
+                writer.write_all(b"c1abc2ab").unwrap();
+            }
+            assert_eq!(output, b"0abc1X2ab");
+        }
+
+        #[test]
+        fn test_hold_back_until_decided() {
+            let mut output = Vec::new();
+            let mut writer = ReplacingWriter::new(&mut output, b"abc", b"X");
+            writer.write_all(b"0abc1ab").unwrap();
+            drop(writer);
+            assert_eq!(output, b"0X1ab");
+
+            let mut output = Vec::new();
+            let mut writer = ReplacingWriter::new(&mut output, b"abc", b"X");
+            writer.write_all(b"0abc1ab").unwrap();
+            std::mem::forget(writer);
+            assert_eq!(output, b"0X1");
+        }
+
+        #[test]
+        fn test_underlying_error_is_retried() {
+            // accepts one byte at a time
+            let mut underlying = TestWriter::new().chunk(1);
+            {
+                let mut writer = ReplacingWriter::new(&mut underlying, b"ab", b"X");
+                assert_eq!(writer.write(b"1ab2").unwrap(), 4);
+            }
+            assert_eq!(underlying.data, b"1X2");
+
+            let mut underlying = TestWriter::new().chunk(1).errors(vec![Some(io::ErrorKind::Other)]);
+            {
+                let mut writer = ReplacingWriter::new(&mut underlying, b"ab", b"X");
+                // accepted, but the underlying writer failed to take it
+                assert_eq!(writer.write(b"1ab2").unwrap(), 4);
+                writer.flush().unwrap();
+            }
+            assert_eq!(underlying.data, b"1X2");
+        }
+    }
+
+    mod test_match_reporting {
+        use crate::conv::{Match, MultiReplacingReader, ReplacingReader, ReplacingWriter};
+        use std::io::{Read, Write};
+
+        #[test]
+        fn test_offsets_map_input_to_output() {
+            let input = "one two three two one".repeat(5);
+            let pairs = [("one", "1"), ("two", "second"), ("three", "")];
+            let mut input_bytes = input.as_bytes();
+            let mut reader = MultiReplacingReader::new(&mut input_bytes, pairs.iter().copied());
+            reader.record_matches(true);
+            let mut output = String::new();
+            reader.read_to_string(&mut output).unwrap();
+
+            assert_eq!(reader.replacement_count(), 25);
+            assert_eq!(reader.matches().len(), 25);
+            for m in reader.matches() {
+                let (old, new) = pairs[m.pattern];
+                assert_eq!(&input[m.input_offset..m.input_offset + m.input_len], old);
+                assert_eq!(&output[m.output_offset..m.output_offset + m.output_len], new);
+            }
+            assert_eq!(
+                reader.matches()[..2],
+                [
+                    Match { pattern: 0, input_offset: 0, input_len: 3, output_offset: 0, output_len: 1 },
+                    Match { pattern: 1, input_offset: 4, input_len: 3, output_offset: 2, output_len: 6 },
+                ]
+            );
+        }
+
+        #[test]
+        fn test_take_matches() {
+            let mut input_bytes = "xaxxaxxxa".as_bytes();
+            let mut reader = ReplacingReader::new(&mut input_bytes, b"a", b"bb");
+            reader.record_matches(true);
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf).unwrap();
+            let first = reader.take_matches();
+            assert!(!first.is_empty());
+            assert!(reader.matches().is_empty());
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            let offsets: Vec<(usize, usize)> = first
+                .iter()
+                .chain(reader.matches())
+                .map(|m| (m.input_offset, m.output_offset))
+                .collect();
+            assert_eq!(offsets, [(1, 1), (4, 5), (8, 10)]);
+        }
+
+        #[test]
+        fn test_count_without_recording() {
+            let mut input_bytes = "aaaa".as_bytes();
+            let mut reader = ReplacingReader::new(&mut input_bytes, b"a", b"");
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            assert_eq!(reader.replacement_count(), 4);
+            assert!(reader.matches().is_empty());
+        }
+
+        #[test]
+        fn test_writer() {
+            let mut output = Vec::new();
+            let matches;
+            {
+                let mut writer = ReplacingWriter::new(&mut output, b"ab", b"xyz");
+                writer.record_matches(true);
+                for chunk in b"1ab2ab3".chunks(1) {
+                    writer.write_all(chunk).unwrap();
+                }
+                writer.flush().unwrap();
+                assert_eq!(writer.replacement_count(), 2);
+                matches = writer.take_matches();
+            }
+            assert_eq!(output, b"1xyz2xyz3");
+            assert_eq!(
+                matches,
+                [
+                    Match { pattern: 0, input_offset: 1, input_len: 2, output_offset: 1, output_len: 3 },
+                    Match { pattern: 0, input_offset: 4, input_len: 2, output_offset: 5, output_len: 3 },
+                ]
+            );
+        }
+    }
+
+    mod test_owned {
+        use crate::conv::{MultiReplacingReader, ReplacingReader, ReplacingWriter};
+        use std::io::{Cursor, Read, Write};
+        use std::thread;
+
+        fn redacting_reader(data: Vec<u8>) -> Box<dyn Read + Send + 'static> {
+            let mut count = 0;
+            Box::new(ReplacingReader::new_with(Cursor::new(data), b"secret", move |_, _| {
+                count += 1;
+                format!("[{}]", count)
+            }))
+        }
+
+        #[test]
+        fn test_boxed_into_thread() {
+            let mut reader = redacting_reader(b"a secret, another secret".to_vec());
+            let output = thread::spawn(move || {
+                let mut output = String::new();
+                reader.read_to_string(&mut output).unwrap();
+                output
+            })
+            .join()
+            .unwrap();
+            assert_eq!(output, "a [1], another [2]");
+        }
+
+        #[test]
+        fn test_reader_accessors() {
+            let mut reader = MultiReplacingReader::new(Cursor::new(b"abc".to_vec()), vec![("b", "x")]);
+            assert_eq!(reader.get_ref().position(), 0);
+            let mut output = String::new();
+            reader.read_to_string(&mut output).unwrap();
+            assert_eq!(output, "axc");
+            reader.get_mut().set_position(1);
+            let inner = reader.into_inner();
+            assert_eq!(inner.position(), 1);
+        }
+
+        #[test]
+        fn test_writer_into_inner() {
+            let mut writer = ReplacingWriter::new(Vec::new(), b"abc", b"X");
+            writer.write_all(b"abc ab").unwrap();
+            assert_eq!(writer.get_ref(), b"X ");
+            let output = writer.into_inner().unwrap();
+            assert_eq!(output, b"X ab");
+        }
+    }
+
+    mod test_pattern_errors {
+        use crate::conv::{MultiReplacingReader, PatternError, ReplacingReader, ReplacingWriter};
+        use std::io::{self, Read};
+
+        #[test]
+        fn test_empty_pattern() {
+            let input: &[u8] = b"abc";
+            assert_eq!(ReplacingReader::try_new(input, b"", b"x").err(), Some(PatternError::EmptyPattern(0)));
+            assert_eq!(ReplacingReader::builder(b"").build(input).err(), Some(PatternError::EmptyPattern(0)));
+            assert_eq!(
+                MultiReplacingReader::try_new(input, vec![("a", "b"), ("", "c")]).err(),
+                Some(PatternError::EmptyPattern(1))
+            );
+            assert_eq!(ReplacingWriter::try_new(io::sink(), b"", b"x").err(), Some(PatternError::EmptyPattern(0)));
+            assert_eq!(PatternError::EmptyPattern(1).to_string(), "pattern 1 can not be empty");
+        }
+
+        #[test]
+        #[should_panic(expected = "pattern 0 can not be empty")]
+        fn test_new_panics() {
+            let input: &[u8] = b"abc";
+            ReplacingReader::new(input, b"", b"x");
+        }
+
+        #[test]
+        fn test_builder() {
+            let input: &[u8] = b"a-b-c";
+            let mut output = String::new();
+            ReplacingReader::builder(b"-").build(input).unwrap().read_to_string(&mut output).unwrap();
+            assert_eq!(output, "abc");
+
+            let mut output = String::new();
+            ReplacingReader::builder(b"-")
+                .replacement(b"+")
+                .build(input)
+                .unwrap()
+                .read_to_string(&mut output)
+                .unwrap();
+            assert_eq!(output, "a+b+c");
+        }
+    }
+
+    mod test_overlapping_pattern {
+        use crate::conv::ReplacingReader;
+        use std::io::Read;
+
+        fn run_string_through(input: &str, old: &str, new: &str) -> String {
+            let mut reader = ReplacingReader::new(input.as_bytes(), old.as_bytes(), new.as_bytes());
+            let mut ret = String::new();
+            reader.read_to_string(&mut ret).unwrap();
+            ret
+        }
+
+        #[test]
+        fn test_self_overlap() {
+            assert_eq!(run_string_through("aaa", "aa", "b"), "ba");
+            assert_eq!(run_string_through("aaaa", "aa", "b"), "bb");
+            assert_eq!(run_string_through("aaaaa", "aa", "aaa"), "aaaaaaa");
+            assert_eq!(run_string_through("abababa", "aba", "x"), "xbx");
+        }
+
+        #[test]
+        fn test_same_as_str_replace() {
+            for old in ["aa", "aba", "abab", "aab", "baab", "aaab"].iter() {
+                // every string over "ab" up to 10 characters long
+                for len in 0..=10u32 {
+                    for n in 0..2usize.pow(len) {
+                        let input: String = (0..len).map(|i| if n >> i & 1 == 0 { 'a' } else { 'b' }).collect();
+                        assert_eq!(run_string_through(&input, old, "_"), input.replace(old, "_"), "input {:?}", input);
+                    }
+                }
+            }
+        }
+    }
+
+    mod test_buf_read {
+        use crate::conv::{MultiReplacingReader, ReplacingReader};
+        use std::io::{self, BufRead, Read};
+
+        #[test]
+        fn test_lines() {
+            let input: &[u8] = b"Host: a\nAccept: b\nHost: c\n";
+            let reader = ReplacingReader::new(input, b"Host", b"X-Forwarded-Host");
+            let lines: Vec<String> = reader.lines().map(Result::unwrap).collect();
+            assert_eq!(lines, ["X-Forwarded-Host: a", "Accept: b", "X-Forwarded-Host: c"]);
+
+            let input: &[u8] = b"a,bb,ccc";
+            let mut reader = MultiReplacingReader::new(input, vec![(",", ";"), ("bb", "B")]);
+            let mut field = Vec::new();
+            reader.read_until(b';', &mut field).unwrap();
+            reader.read_until(b';', &mut field).unwrap();
+            assert_eq!(field, b"a;B;");
+        }
+
+        #[test]
+        fn test_fill_buf_and_consume() {
+            let input = "0123456789".repeat(100);
+            let mut reader = ReplacingReader::builder(b"89")
+                .replacement(b"_")
+                .buffer_size(4096)
+                .build(input.as_bytes())
+                .unwrap();
+            let buf = reader.fill_buf().unwrap();
+            assert_eq!(buf.len(), 900);
+            assert!(buf.starts_with(b"01234567_0"));
+            reader.consume(895);
+            let mut rest = String::new();
+            reader.read_to_string(&mut rest).unwrap();
+            assert_eq!(rest, "4567_");
+            assert!(reader.fill_buf().unwrap().is_empty());
+        }
+
+        #[test]
+        fn test_buffer_smaller_than_pattern() {
+            let input: &[u8] = b"xabcdxabcd";
+            let mut reader = ReplacingReader::builder(b"abcd").replacement(b"-").buffer_size(1).build(input).unwrap();
+            let mut output = String::new();
+            reader.read_to_string(&mut output).unwrap();
+            assert_eq!(output, "x-x-");
+        }
+
+        // returns the first chunk, then fails as if no more data is there yet
+        struct StallingReader {
+            chunk: Option<&'static [u8]>,
+        }
+
+        impl Read for StallingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.chunk.take() {
+                    Some(chunk) => {
+                        buf[..chunk.len()].copy_from_slice(chunk);
+                        Ok(chunk.len())
+                    }
+                    None => Err(io::Error::new(io::ErrorKind::WouldBlock, "stalled")),
+                }
+            }
+        }
+
+        #[test]
+        fn test_does_not_wait_for_full_buffer() {
+            let input = StallingReader { chunk: Some(b"PING x\n") };
+            let mut reader = ReplacingReader::builder(b"PING").replacement(b"PONG").buffer_size(1024).build(input).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "PONG x\n");
+            assert_eq!(reader.read_line(&mut line).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        }
+    }
+
+    mod test_case_matching {
+        use crate::conv::{CaseMatching, PatternError, ReplacingReader};
+        use std::io::{self, Read};
+
+        #[test]
+        fn test_default_is_sensitive() {
+            assert_eq!(CaseMatching::default(), CaseMatching::Sensitive);
+        }
+
+        // hands out one byte at a time, to split characters across reads
+        struct ByteReader<'a>(&'a [u8]);
+
+        impl Read for ByteReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = self.0.len().min(buf.len()).min(1);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        fn run_string_through(input: &str, old: &str, new: &str, case: CaseMatching) -> String {
+            let mut outputs = Vec::new();
+            for &buffer_size in [1, 16, 4096].iter() {
+                let mut reader = ReplacingReader::builder(old.as_bytes())
+                    .replacement(new.as_bytes())
+                    .case_matching(case)
+                    .buffer_size(buffer_size)
+                    .build(ByteReader(input.as_bytes()))
+                    .unwrap();
+                let mut ret = String::new();
+                reader.read_to_string(&mut ret).unwrap();
+                outputs.push(ret);
+            }
+            assert!(outputs.iter().all(|output| *output == outputs[0]));
+            outputs.pop().unwrap()
+        }
+
+        #[test]
+        fn test_sensitive_by_default() {
+            let input: &[u8] = b"Host host HOST";
+            let mut output = String::new();
+            ReplacingReader::builder(b"host").replacement(b"x").build(input).unwrap().read_to_string(&mut output).unwrap();
+            assert_eq!(output, "Host x HOST");
+        }
+
+        #[test]
+        fn test_ascii_insensitive() {
+            let case = CaseMatching::AsciiInsensitive;
+            assert_eq!(run_string_through("Host: a\nHOST: b\nhOsT: c", "host", "Via", case), "Via: a\nVia: b\nVia: c");
+            assert_eq!(run_string_through("Straße STRASSE", "straße", "x", case), "x STRASSE");
+            assert_eq!(run_string_through("STRAẞE", "straße", "x", case), "STRAẞE");
+        }
+
+        #[test]
+        fn test_unicode_insensitive() {
+            let case = CaseMatching::UnicodeInsensitive;
+            assert_eq!(run_string_through("Straße STRAẞE STRASSE", "straße", "x", case), "x x STRASSE");
+            assert_eq!(run_string_through("ΣΊΣΥΦΟΣ σίσυφος σίσυφοσ", "σίσυφος", "x", case), "x x x");
+            // the Kelvin sign is 3 bytes long and folds to a plain k
+            assert_eq!(run_string_through("5 \u{212A}g, 6 kg, 7 KG", "kg", "kilograms", case), "5 kilograms, 6 kilograms, 7 kilograms");
+            assert_eq!(run_string_through("tÄst TäST täst", "Täst", "-", case), "- - -");
+        }
+
+        #[test]
+        fn test_unicode_invalid_input_passes_through() {
+            let input: &[u8] = b"\xffAB\xc3\xa4\xc3ab\xe2\x82";
+            let mut reader = ReplacingReader::builder(b"ab")
+                .replacement(b"_")
+                .case_matching(CaseMatching::UnicodeInsensitive)
+                .build(input)
+                .unwrap();
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            assert_eq!(output, b"\xff_\xc3\xa4\xc3_\xe2\x82");
+        }
+
+        #[test]
+        fn test_unicode_pattern_must_be_utf8() {
+            let input: &[u8] = b"";
+            let result = ReplacingReader::builder(b"a\xff").case_matching(CaseMatching::UnicodeInsensitive).build(input);
+            assert_eq!(result.err(), Some(PatternError::InvalidUtf8(0)));
+        }
+    }
+
+    mod test_limits {
+        use crate::conv::{ReplacingReader, ReplacingReaderBuilder};
+        use std::io::Read;
+        use std::ops::Bound;
+
+        fn run_through(input: &[u8], builder: ReplacingReaderBuilder) -> (Vec<u8>, bool) {
+            let mut reader = builder.buffer_size(3).build(input).unwrap();
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            (output, reader.limit_reached())
+        }
+
+        #[test]
+        fn test_max_replacements() {
+            let builder = ReplacingReader::builder(b"ab").replacement(b"X");
+            assert_eq!(run_through(b"ab-ab-ab-ab", builder.max_replacements(2)), (b"X-X-ab-ab".to_vec(), true));
+
+            let builder = ReplacingReader::builder(b"ab").replacement(b"X");
+            assert_eq!(run_through(b"ab-ab", builder.max_replacements(3)), (b"X-X".to_vec(), false));
+
+            let builder = ReplacingReader::builder(b"ab").replacement(b"X");
+            assert_eq!(run_through(b"ab-ab", builder.max_replacements(0)), (b"ab-ab".to_vec(), true));
+        }
+
+        #[test]
+        fn test_range() {
+            let input = b"\x00\x01\x00\x01\x00\x01\x00\x01";
+            let patch = || ReplacingReader::builder(b"\x00\x01").replacement(b"\xff\xff");
+
+            let (output, reached) = run_through(input, patch().range(2..6));
+            assert_eq!(output, b"\x00\x01\xff\xff\xff\xff\x00\x01");
+            assert!(reached);
+
+            // occurrences straddling the range are left alone
+            let (output, _) = run_through(input, patch().range(1..=6));
+            assert_eq!(output, b"\x00\x01\xff\xff\xff\xff\x00\x01");
+
+            let (output, reached) = run_through(input, patch().range(4..));
+            assert_eq!(output, b"\x00\x01\x00\x01\xff\xff\xff\xff");
+            assert!(!reached);
+
+            let (output, reached) = run_through(input, patch().range(..3).max_replacements(5));
+            assert_eq!(output, b"\xff\xff\x00\x01\x00\x01\x00\x01");
+            assert!(reached);
+        }
+
+        #[test]
+        fn test_range_at_max() {
+            let patch = || ReplacingReader::builder(b"a").replacement(b"b");
+            assert_eq!(run_through(b"aaaa", patch().range(..=usize::MAX)), (b"bbbb".to_vec(), false));
+            assert_eq!(run_through(b"aaaa", patch().range(1..=usize::MAX)), (b"abbb".to_vec(), false));
+            let excluded = (Bound::Excluded(usize::MAX), Bound::Unbounded);
+            assert_eq!(run_through(b"aaaa", patch().range(excluded)), (b"aaaa".to_vec(), true));
+        }
+
+        #[test]
+        fn test_range_and_max_replacements() {
+            let builder = ReplacingReader::builder(b"a").replacement(b"b").range(2..).max_replacements(2);
+            assert_eq!(run_through(b"aaaaaa", builder), (b"aabbaa".to_vec(), true));
+        }
+
+        #[test]
+        fn test_limit_reached_while_reading() {
+            let input: &[u8] = b"a...a...........";
+            let mut reader = ReplacingReader::builder(b"a").replacement(b"b").max_replacements(1).build(input).unwrap();
+            assert!(!reader.limit_reached());
+            let mut buf = [0u8; 1];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, *b"b");
+            assert!(reader.limit_reached());
+        }
+    }
 }
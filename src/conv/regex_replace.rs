@@ -0,0 +1,280 @@
+use crate::read_full;
+use regex::bytes::Regex;
+use std::io::{self, Read};
+
+// number of already served bytes kept in front of the search position, so that assertions
+// like \b and (?m)^ can look at the previous character (at most 4 bytes in UTF-8)
+const CONTEXT_LEN: usize = 4;
+
+/// RegexReplacingReader wraps around an underlying reader and transiently replaces matches of a regular expression in the read.
+///
+/// The replacement may refer to capture groups with `$1`, `$name` or `${name}`, see `regex::bytes::Captures::expand`.
+///
+/// Matching only ever looks at a bounded window of the stream: a match is decided once `window` bytes
+/// past its start are buffered. As long as no match is longer than `window` bytes, the output is the same as
+/// `Regex::replace_all` over the whole stream. Longer matches may be cut short or missed.
+/// The internal buffer is 2 * window, caller can wrap std::io::BufReader if more buffer is required.
+///
+/// A runtime panic will be thrown if window == 0.
+pub struct RegexReplacingReader<R> {
+    underlying_reader: R,
+    regex: Regex,
+    replacement: Vec<u8>,
+    window: usize,
+
+    // buffer[..search_ptr] has already been decided on and is only kept as context for the regex
+    buffer: Vec<u8>,
+    search_ptr: usize,
+
+    // the end of the last match, an empty match is not allowed to start right there
+    last_match_end: Option<usize>,
+
+    // rewritten bytes waiting to be served, starting at output_ptr
+    output: Vec<u8>,
+    output_ptr: usize,
+
+    eof: bool,
+    // the search reached the end of stream, nothing more will be decided
+    done: bool,
+}
+
+impl<R: Read> RegexReplacingReader<R> {
+    /// Compiles `pattern` and creates a reader replacing its matches with `replacement`.
+    pub fn new(r: R, pattern: &str, replacement: &[u8], window: usize) -> Result<RegexReplacingReader<R>, regex::Error> {
+        Ok(RegexReplacingReader::with_regex(r, Regex::new(pattern)?, replacement, window))
+    }
+
+    /// Creates a reader replacing matches of an already compiled `regex` with `replacement`.
+    pub fn with_regex(r: R, regex: Regex, replacement: &[u8], window: usize) -> RegexReplacingReader<R> {
+        if window == 0 { panic!("match window can not be empty") };
+
+        RegexReplacingReader {
+            underlying_reader: r,
+            regex,
+            replacement: replacement.to_vec(),
+            window,
+            buffer: Vec::with_capacity(CONTEXT_LEN + 2 * window),
+            search_ptr: 0,
+            last_match_end: None,
+            output: Vec::new(),
+            output_ptr: 0,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.underlying_reader
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading directly from it skips over the data, and may corrupt the replacement.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.underlying_reader
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    ///
+    /// Data already read from the underlying reader and not yet served is lost.
+    pub fn into_inner(self) -> R {
+        self.underlying_reader
+    }
+
+    // moves as much of buffer[search_ptr..] to output as can be decided with the data at hand.
+    fn process(&mut self) {
+        loop {
+            if self.search_ptr == self.buffer.len() && !self.eof {
+                return;
+            }
+            let (start, end) = match self.regex.captures_at(&self.buffer, self.search_ptr) {
+                Some(caps) => {
+                    let m = caps.get(0).unwrap();
+                    if !self.eof && m.start() + self.window >= self.buffer.len() {
+                        // the match could still turn out differently, serve what is before it
+                        let safe_end = self.buffer.len().saturating_sub(self.window).min(m.start());
+                        self.skip_to(safe_end);
+                        return;
+                    }
+                    if m.start() == m.end() && self.last_match_end == Some(m.start()) {
+                        // an empty match right after the previous match does not count
+                        (m.start(), None)
+                    } else {
+                        self.output.extend_from_slice(&self.buffer[self.search_ptr..m.start()]);
+                        caps.expand(&self.replacement, &mut self.output);
+                        (m.start(), Some(m.end()))
+                    }
+                }
+                None => {
+                    let safe_end = if self.eof {
+                        self.buffer.len()
+                    } else {
+                        self.buffer.len().saturating_sub(self.window)
+                    };
+                    self.skip_to(safe_end);
+                    self.done = self.eof;
+                    return;
+                }
+            };
+
+            match end {
+                Some(end) => {
+                    self.search_ptr = end;
+                    self.last_match_end = Some(end);
+                }
+                None if start == self.buffer.len() => {
+                    self.done = true;
+                    return;
+                }
+                None => self.skip_to(start + 1),
+            }
+        }
+    }
+
+    // serves buffer[search_ptr..end] unchanged
+    fn skip_to(&mut self, end: usize) {
+        if end > self.search_ptr {
+            self.output.extend_from_slice(&self.buffer[self.search_ptr..end]);
+            self.search_ptr = end;
+        }
+    }
+
+    // drops decided bytes except for the context, and tops up the buffer from underlying_reader
+    fn fill(&mut self) -> Result<(), io::Error> {
+        let drop_len = self.search_ptr.saturating_sub(CONTEXT_LEN);
+        self.buffer.drain(..drop_len);
+        self.search_ptr -= drop_len;
+        self.last_match_end = self.last_match_end.and_then(|end| end.checked_sub(drop_len));
+
+        let filled = self.buffer.len();
+        let capacity = self.search_ptr + 2 * self.window;
+        self.buffer.resize(capacity, 0);
+        match read_full(&mut self.buffer[filled..], &mut self.underlying_reader) {
+            Ok(size) => {
+                self.buffer.truncate(filled + size);
+                self.eof = self.buffer.len() < capacity;
+                Ok(())
+            }
+            Err(e) => {
+                // keep what was read, the next fill goes on from there
+                self.buffer.truncate(filled + e.bytes_read);
+                Err(e.source)
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for RegexReplacingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        loop {
+            let available = self.output.len() - self.output_ptr;
+            if available > 0 {
+                let size = available.min(buf.len());
+                buf[..size].copy_from_slice(&self.output[self.output_ptr..self.output_ptr + size]);
+                self.output_ptr += size;
+                return Ok(size);
+            }
+            if self.done {
+                return Ok(0);
+            }
+
+            self.output.clear();
+            self.output_ptr = 0;
+            if !self.eof {
+                self.fill()?;
+            }
+            self.process();
+        }
+    }
+}
+
+#[cfg(test)]
+mod testconv {
+    mod test_regex_replacing_reader {
+        use crate::conv::RegexReplacingReader;
+        use crate::tests::ScriptedReader;
+        use regex::bytes::Regex;
+        use std::io::{self, Read};
+
+        fn run_string_through(input: &str, pattern: &str, replacement: &str, window: usize) -> String {
+            let mut input_bytes = input.as_bytes();
+            let mut reader =
+                RegexReplacingReader::new(&mut input_bytes, pattern, replacement.as_bytes(), window).unwrap();
+            let mut ret = String::new();
+            reader.read_to_string(&mut ret).unwrap();
+            ret
+        }
+
+        fn replace_all(input: &str, pattern: &str, replacement: &str) -> String {
+            let regex = Regex::new(pattern).unwrap();
+            String::from_utf8(regex.replace_all(input.as_bytes(), replacement.as_bytes()).into_owned()).unwrap()
+        }
+
+        #[test]
+        fn test_capture_groups() {
+            let input = "alice=1, bob=22, carol=333";
+            assert_eq!(
+                run_string_through(input, r"(?P<key>\w+)=(\d+)", "$2:${key}", 16),
+                "1:alice, 22:bob, 333:carol"
+            );
+        }
+
+        #[test]
+        fn test_same_as_replace_all() {
+            let input = "ab aab abb aaabbb ba a-b the cat sat on the mat. ".repeat(20);
+            let cases = [
+                ("a+b", "X"),
+                (r"\bthe\b", "THE"),
+                (r"\w+at", "<$0>"),
+                ("b*", "-"),
+                (r"(?m)^ab|\.\s", "|"),
+                ("a|aab", "_"),
+            ];
+            for &(pattern, replacement) in cases.iter() {
+                for window in 8..12 {
+                    assert_eq!(
+                        run_string_through(&input, pattern, replacement, window),
+                        replace_all(&input, pattern, replacement),
+                        "pattern {:?} window {}",
+                        pattern,
+                        window
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_empty_input() {
+            assert_eq!(run_string_through("", "a", "b", 4), "");
+            assert_eq!(run_string_through("", "a*", "b", 4), "b");
+        }
+
+        #[test]
+        fn test_match_longer_than_window() {
+            // only the first `window` bytes are guaranteed to be looked at
+            let out = run_string_through("aaaaaaaaaa", "a+", "X", 3);
+            assert!(out.chars().all(|c| c == 'X' || c == 'a'));
+            assert!(out.starts_with('X'));
+        }
+
+        #[test]
+        fn test_invalid_pattern() {
+            let mut input_bytes = "abc".as_bytes();
+            assert!(RegexReplacingReader::new(&mut input_bytes, "(", b"", 4).is_err());
+        }
+
+        #[test]
+        fn test_underlying_error() {
+            let input = ScriptedReader::new(vec![Ok(b"a-a"), Err(io::ErrorKind::ConnectionReset), Ok(b"-a")]);
+            let mut reader = RegexReplacingReader::new(input, "a", b"X", 8).unwrap();
+            let mut ret = String::new();
+            let err = reader.read_to_string(&mut ret).unwrap_err();
+            // the error of the underlying reader is returned as is
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(err.to_string(), "scripted");
+            reader.read_to_string(&mut ret).unwrap();
+            assert_eq!(ret, "X-X-X");
+        }
+    }
+}
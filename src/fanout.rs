@@ -0,0 +1,310 @@
+use crate::write_full;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// TeeReader wraps around a reader and writes everything read from it to a writer, like Go's io.TeeReader.
+///
+/// Each read is written whole to the writer before it returns. A failed write is returned as the error
+/// of the read, and the data of that read is lost to the caller, the error tells how much of it the writer got.
+pub struct TeeReader<R, W> {
+    underlying_reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(r: R, w: W) -> TeeReader<R, W> {
+        TeeReader { underlying_reader: r, writer: w }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.underlying_reader
+    }
+
+    /// Gets a mutable reference to the underlying reader, reading from it directly is not mirrored.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.underlying_reader
+    }
+
+    /// Gets a reference to the writer.
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// Gets a mutable reference to the writer.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Unwraps this reader, returning the underlying reader and the writer.
+    pub fn into_inner(self) -> (R, W) {
+        (self.underlying_reader, self.writer)
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let size = self.underlying_reader.read(buf)?;
+        write_full(&buf[..size], &mut self.writer)?;
+        Ok(size)
+    }
+}
+
+/// FanOutPolicy selects what a MultiWriter does when some of its sinks fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanOutPolicy {
+    /// The first failing sink fails the write, the sinks after it are not written to. This is the default.
+    FailFast,
+    /// A failing sink is left out from then on, and its error is kept in MultiWriter::errors().
+    /// Writes fail only once all sinks have failed, the first such write returns the errors kept so far.
+    BestEffort,
+    /// Every sink is written to, and the write fails with the errors of all failing sinks.
+    /// Failing sinks are tried again on the next write, while the others get the data again if the caller retries.
+    CollectErrors,
+}
+
+/// SinkError is the error of one sink of a MultiWriter.
+#[derive(Debug)]
+pub struct SinkError {
+    /// The index of the sink.
+    pub sink: usize,
+    /// Bytes of the failed write or flush the sink accepted before the error.
+    pub bytes_written: usize,
+    pub source: io::Error,
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sink {}: {} after writing {} bytes", self.sink, self.source, self.bytes_written)
+    }
+}
+
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// MultiWriteError is the error of a MultiWriter write or flush, holding the errors of the sinks that failed it.
+///
+/// Returned inside an io::Error of the kind of the first sink error, see `downcast_ref::<MultiWriteError>()`.
+#[derive(Debug)]
+pub struct MultiWriteError {
+    pub errors: Vec<SinkError>,
+}
+
+impl fmt::Display for MultiWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.errors.len() {
+            0 => write!(f, "no sink to write to"),
+            1 => write!(f, "{}", self.errors[0]),
+            n => write!(f, "{} sinks failed, first {}", n, self.errors[0]),
+        }
+    }
+}
+
+impl std::error::Error for MultiWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.errors.first().map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<MultiWriteError> for io::Error {
+    fn from(e: MultiWriteError) -> io::Error {
+        let kind = e.errors.first().map_or(io::ErrorKind::Other, |e| e.source.kind());
+        io::Error::new(kind, e)
+    }
+}
+
+/// MultiWriter duplicates every write to all of its sinks, like Go's io.MultiWriter.
+///
+/// Each write is written whole to every sink, retrying interrupted and partial writes,
+/// and returns the full length of the buffer. Sink failures are handled by the FanOutPolicy.
+pub struct MultiWriter<W> {
+    sinks: Vec<W>,
+    policy: FanOutPolicy,
+    // failed[i] is true if sink i was left out by the BestEffort policy
+    failed: Vec<bool>,
+    errors: Vec<SinkError>,
+}
+
+impl<W: Write> MultiWriter<W> {
+    pub fn new(sinks: Vec<W>) -> MultiWriter<W> {
+        let failed = vec![false; sinks.len()];
+        MultiWriter { sinks, policy: FanOutPolicy::FailFast, failed, errors: Vec::new() }
+    }
+
+    pub fn set_policy(&mut self, policy: FanOutPolicy) {
+        self.policy = policy;
+    }
+
+    /// Returns the errors of the sinks left out by the BestEffort policy, until the write failing on all sinks takes them.
+    pub fn errors(&self) -> &[SinkError] {
+        &self.errors
+    }
+
+    /// Returns true if sink `index` was left out by the BestEffort policy.
+    pub fn is_failed(&self, index: usize) -> bool {
+        self.failed[index]
+    }
+
+    /// Gets references to the sinks.
+    pub fn get_ref(&self) -> &[W] {
+        &self.sinks
+    }
+
+    /// Gets mutable references to the sinks, writing to them directly is not duplicated.
+    pub fn get_mut(&mut self) -> &mut [W] {
+        &mut self.sinks
+    }
+
+    /// Unwraps this writer, returning the sinks.
+    pub fn into_inner(self) -> Vec<W> {
+        self.sinks
+    }
+
+    // runs op on every sink that is not left out, handling errors by the policy.
+    fn for_each_sink<F>(&mut self, mut op: F) -> io::Result<()>
+    where
+        F: FnMut(&mut W) -> Result<(), (usize, io::Error)>,
+    {
+        let mut errors = Vec::new();
+        for (sink, w) in self.sinks.iter_mut().enumerate() {
+            if self.failed[sink] {
+                continue;
+            }
+            if let Err((bytes_written, source)) = op(w) {
+                let error = SinkError { sink, bytes_written, source };
+                match self.policy {
+                    FanOutPolicy::FailFast => return Err(MultiWriteError { errors: vec![error] }.into()),
+                    FanOutPolicy::BestEffort => {
+                        self.failed[sink] = true;
+                        self.errors.push(error);
+                    }
+                    FanOutPolicy::CollectErrors => errors.push(error),
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(MultiWriteError { errors }.into());
+        }
+        if self.policy == FanOutPolicy::BestEffort && self.failed.iter().all(|&failed| failed) && !self.sinks.is_empty() {
+            // nothing was written anywhere
+            return Err(MultiWriteError { errors: std::mem::take(&mut self.errors) }.into());
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for MultiWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.for_each_sink(|w| write_full(buf, w).map(|_| ()).map_err(|e| (e.bytes_written, e.source)))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.for_each_sink(|w| w.flush().map_err(|e| (0, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod test_fanout {
+        use crate::fanout::{FanOutPolicy, MultiWriteError, MultiWriter, TeeReader};
+        use crate::tests::TestWriter;
+        use std::io::{self, Read, Write};
+
+        // accepts `capacity` bytes, then fails
+        fn full_at(capacity: usize) -> TestWriter {
+            TestWriter::new().capacity(capacity, Some(io::ErrorKind::StorageFull))
+        }
+
+        fn multi_error(e: &io::Error) -> &MultiWriteError {
+            e.get_ref().unwrap().downcast_ref::<MultiWriteError>().unwrap()
+        }
+
+        #[test]
+        fn test_tee_reader() {
+            let input = "the quick brown fox".as_bytes();
+            let mut tee = TeeReader::new(input, Vec::new());
+            let mut out = String::new();
+            tee.read_to_string(&mut out).unwrap();
+            assert_eq!(out, "the quick brown fox");
+            let (_, copy) = tee.into_inner();
+            assert_eq!(copy, input);
+        }
+
+        #[test]
+        fn test_tee_reader_write_error() {
+            let mut tee = TeeReader::new(&b"0123456789"[..], full_at(4));
+            let mut buf = [0u8; 6];
+            let err = tee.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+            let partial = err.get_ref().unwrap().downcast_ref::<crate::PartialWriteError>().unwrap();
+            assert_eq!(partial.bytes_written, 4);
+            assert_eq!(tee.writer().data, b"0123");
+        }
+
+        #[test]
+        fn test_multi_writer() {
+            let mut multi = MultiWriter::new(vec![Vec::new(), Vec::new(), Vec::new()]);
+            multi.write_all(b"hello ").unwrap();
+            write!(multi, "{}", 42).unwrap();
+            multi.flush().unwrap();
+            for sink in multi.into_inner() {
+                assert_eq!(sink, b"hello 42");
+            }
+        }
+
+        #[test]
+        fn test_fail_fast() {
+            let mut multi = MultiWriter::new(vec![full_at(10), full_at(3), full_at(10)]);
+            let err = multi.write(b"01234").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+            let errors = &multi_error(&err).errors;
+            assert_eq!(errors.len(), 1);
+            assert_eq!((errors[0].sink, errors[0].bytes_written), (1, 3));
+            let sinks = multi.get_ref();
+            assert_eq!(sinks[0].data, b"01234");
+            assert_eq!(sinks[1].data, b"012");
+            assert!(sinks[2].data.is_empty());
+        }
+
+        #[test]
+        fn test_best_effort() {
+            let mut multi = MultiWriter::new(vec![full_at(2), full_at(10), full_at(4)]);
+            multi.set_policy(FanOutPolicy::BestEffort);
+            assert_eq!(multi.write(b"012").unwrap(), 3);
+            assert_eq!(multi.write(b"345").unwrap(), 3);
+            assert!(multi.is_failed(0) && !multi.is_failed(1) && multi.is_failed(2));
+            let errors: Vec<(usize, usize)> = multi.errors().iter().map(|e| (e.sink, e.bytes_written)).collect();
+            assert_eq!(errors, vec![(0, 2), (2, 1)]);
+            assert_eq!(multi.get_ref()[1].data, b"012345");
+            // the last sink standing fails too
+            multi.write_all(b"6789").unwrap();
+            let err = multi.write(b"x").unwrap_err();
+            assert_eq!(multi_error(&err).errors.len(), 3);
+            let err = multi.write(b"x").unwrap_err();
+            assert!(multi_error(&err).errors.is_empty());
+        }
+
+        #[test]
+        fn test_collect_errors() {
+            let mut multi = MultiWriter::new(vec![full_at(2), full_at(10), full_at(4)]);
+            multi.set_policy(FanOutPolicy::CollectErrors);
+            let err = multi.write(b"01234").unwrap_err();
+            let errors: Vec<(usize, usize)> = multi_error(&err).errors.iter().map(|e| (e.sink, e.bytes_written)).collect();
+            assert_eq!(errors, vec![(0, 2), (2, 4)]);
+            assert_eq!(multi.get_ref()[1].data, b"01234");
+            assert!(multi.errors().is_empty());
+            assert!(err.to_string().starts_with("2 sinks failed, first sink 0"));
+        }
+
+        #[test]
+        fn test_boxed_sinks() {
+            let sinks: Vec<Box<dyn Write>> = vec![Box::new(io::sink()), Box::new(Vec::new())];
+            let mut multi = MultiWriter::new(sinks);
+            multi.write_all(b"abc").unwrap();
+        }
+    }
+}
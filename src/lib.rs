@@ -1,15 +1,64 @@
-use std::io::{self, Read, Write};
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc};
+use std::sync::{Arc, Mutex, OnceLock};
 
+pub mod clock;
+pub mod concat;
 pub mod conv;
+pub mod fanout;
+pub mod pipe;
+pub mod progress;
+pub mod section;
+pub mod stats;
+pub mod throttle;
+
+use clock::Clock;
+use progress::ProgressReporter;
+use stats::{MeterStats, StatsRecorder};
+
+// defines an error carrying an io::Error along with the progress made before it, and converting into an io::Error.
+macro_rules! partial_error {
+    ($(#[$doc:meta])* $name:ident, $(#[$count_doc:meta])* $count:ident: $count_type:ty, $verb:literal) => {
+        $(#[$doc])*
+        ///
+        #[doc = concat!("Converts into an io::Error of the same kind, see `downcast_ref::<", stringify!($name), ">()`.")]
+        #[derive(Debug)]
+        pub struct $name {
+            $(#[$count_doc])*
+            pub $count: $count_type,
+            pub source: io::Error,
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!("{} after ", $verb, " {} bytes"), self.source, self.$count)
+            }
+        }
+
+        impl std::error::Error for $name {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.source)
+            }
+        }
+
+        impl From<$name> for io::Error {
+            fn from(e: $name) -> io::Error {
+                io::Error::new(e.source.kind(), e)
+            }
+        }
+    };
+}
 
 /// read_full reads from r until buffer is full, EOF is met, or an io:Error occured.
 ///
 /// On ok return:
 /// If return size == buffer.len() the read is successful and there may be more data available from r.
 /// If return size < buffer.len(), EOF is met before buffer is filled.
-pub fn read_full(buffer: &mut [u8], r: &mut dyn Read) -> Result<usize, io::Error> {
+///
+/// Reads failing with ErrorKind::Interrupted are retried. On any other error, the bytes already placed
+/// in buffer are reported by the PartialReadError.
+pub fn read_full<R: Read + ?Sized>(buffer: &mut [u8], r: &mut R) -> Result<usize, PartialReadError> {
     let mut len_read: usize = 0;
     loop {
         match r.read(&mut buffer[len_read..]) {
@@ -19,9 +68,111 @@ pub fn read_full(buffer: &mut [u8], r: &mut dyn Read) -> Result<usize, io::Error
                     return Ok(len_read);
                 }
             }
-            Err(e) => return Err(e),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(PartialReadError { bytes_read: len_read, source: e }),
+        }
+    }
+}
+
+partial_error! {
+    /// PartialReadError is an io::Error met by read_full or read_at_least, along with the count of bytes read before it.
+    PartialReadError,
+    /// Bytes placed at the start of the buffer before the error.
+    bytes_read: usize,
+    "reading"
+}
+
+/// read_at_least reads from r into buffer until at least min bytes are read, EOF is met, or an io:Error occured.
+///
+/// On ok return, the size is at least min. Meeting EOF before min bytes is an error of kind UnexpectedEof,
+/// and like any other error it reports the bytes already placed in buffer by a PartialReadError.
+/// Reads failing with ErrorKind::Interrupted are retried.
+///
+/// A runtime panic will be thrown if min > buffer.len().
+pub fn read_at_least<R: Read + ?Sized>(buffer: &mut [u8], min: usize, r: &mut R) -> Result<usize, PartialReadError> {
+    if min > buffer.len() { panic!("buffer is shorter than min") };
+
+    let mut len_read: usize = 0;
+    while len_read < min {
+        match r.read(&mut buffer[len_read..]) {
+            Ok(0) => {
+                let source = io::Error::new(io::ErrorKind::UnexpectedEof, "EOF met before min bytes were read");
+                return Err(PartialReadError { bytes_read: len_read, source });
+            }
+            Ok(size) => len_read += size,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(PartialReadError { bytes_read: len_read, source: e }),
         }
     }
+    Ok(len_read)
+}
+
+/// copy_n copies exactly n bytes from r to w, and returns n.
+///
+/// Meeting EOF before n bytes is an error of kind UnexpectedEof. On any error, the PartialCopyError
+/// reports the bytes written to w before it. Reads and writes failing with ErrorKind::Interrupted are retried,
+/// a write accepting 0 bytes is an error of kind WriteZero.
+pub fn copy_n<R, W>(r: &mut R, w: &mut W, n: u64) -> Result<u64, PartialCopyError>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut buffer = [0u8; 8192];
+    let mut copied: u64 = 0;
+    while copied < n {
+        let len = (n - copied).min(buffer.len() as u64) as usize;
+        let size = match r.read(&mut buffer[..len]) {
+            Ok(0) => {
+                let source = io::Error::new(io::ErrorKind::UnexpectedEof, "EOF met before n bytes were copied");
+                return Err(PartialCopyError { bytes_copied: copied, source });
+            }
+            Ok(size) => size,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(PartialCopyError { bytes_copied: copied, source: e }),
+        };
+        if let Err(e) = write_full(&buffer[..size], w) {
+            return Err(PartialCopyError { bytes_copied: copied + e.bytes_written as u64, source: e.source });
+        }
+        copied += size as u64;
+    }
+    Ok(copied)
+}
+
+partial_error! {
+    /// PartialCopyError is an io::Error met by copy_n, along with the count of bytes copied before it.
+    PartialCopyError,
+    /// Bytes written to the writer before the error.
+    bytes_copied: u64,
+    "copying"
+}
+
+/// write_full writes the whole buffer to w, and returns buffer.len().
+///
+/// Writes failing with ErrorKind::Interrupted are retried, a write accepting 0 bytes is an error of kind WriteZero.
+/// On any error, the PartialWriteError reports the bytes of buffer written before it, so that the caller
+/// can resume with the rest.
+pub fn write_full<W: Write + ?Sized>(buffer: &[u8], w: &mut W) -> Result<usize, PartialWriteError> {
+    let mut len_written: usize = 0;
+    while len_written < buffer.len() {
+        match w.write(&buffer[len_written..]) {
+            Ok(0) => {
+                let source = io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer");
+                return Err(PartialWriteError { bytes_written: len_written, source });
+            }
+            Ok(size) => len_written += size,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(PartialWriteError { bytes_written: len_written, source: e }),
+        }
+    }
+    Ok(len_written)
+}
+
+partial_error! {
+    /// PartialWriteError is an io::Error met by write_full, along with the count of bytes written before it.
+    PartialWriteError,
+    /// Bytes from the start of the buffer written before the error.
+    bytes_written: usize,
+    "writing"
 }
 
 /// BlackHole implements io::Write trait.
@@ -37,59 +188,452 @@ impl Write for BlackHole {
     }
 }
 
-struct MeteringReaderHandle<'a> {
-    underlying_reader: &'a mut dyn Read,
+/// Zero implements io::Read trait.
+///
+/// Reads from Zero never end and fill the whole buffer with zeros.
+pub struct Zero {}
+impl Read for Zero {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+}
+
+/// Repeat implements io::Read trait.
+///
+/// Reads from Repeat never end and fill the whole buffer with the pattern over and over,
+/// each read going on where the previous one stopped.
+pub struct Repeat {
+    pattern: Vec<u8>,
+    pos: usize,
+}
+
+impl Repeat {
+    /// A runtime panic will be thrown if pattern is empty.
+    pub fn new(pattern: &[u8]) -> Repeat {
+        if pattern.is_empty() { panic!("pattern can not be empty") };
+
+        Repeat { pattern: pattern.to_vec(), pos: 0 }
+    }
+}
+
+impl Read for Repeat {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let size = (self.pattern.len() - self.pos).min(buf.len() - filled);
+            buf[filled..filled + size].copy_from_slice(&self.pattern[self.pos..self.pos + size]);
+            filled += size;
+            self.pos = (self.pos + size) % self.pattern.len();
+        }
+        Ok(filled)
+    }
+}
+
+/// Random implements io::Read trait.
+///
+/// Reads from Random never end and fill the whole buffer with pseudo random bytes (SplitMix64).
+/// The stream only depends on the seed, not on how it is read, so it is reproducible in tests and benchmarks.
+/// It is not suitable for anything cryptographic.
+pub struct Random {
+    state: u64,
+    // bytes of the last generated word not yet served, at the end of word
+    word: [u8; 8],
+    available: usize,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed, word: [0; 8], available: 0 }
+    }
+
+    fn next_word(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Read for Random {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.available == 0 {
+                self.word = self.next_word().to_le_bytes();
+                self.available = 8;
+            }
+            let start = 8 - self.available;
+            let size = self.available.min(buf.len() - filled);
+            buf[filled..filled + size].copy_from_slice(&self.word[start..start + size]);
+            filled += size;
+            self.available -= size;
+        }
+        Ok(filled)
+    }
+}
+
+/// MeterHandle observes the byte count of a MeteringReader or MeteringWriter, it can be cloned and sent to other threads.
+///
+/// The count keeps accumulating while the meter is in use, a handle stays valid after the meter is dropped
+/// and then reports the final count.
+#[derive(Clone)]
+pub struct MeterHandle {
     counter: Arc<AtomicUsize>,
+    stats: SharedStats,
+}
+
+impl MeterHandle {
+    pub fn get_counter(&self) -> usize {
+        self.counter.load(Ordering::Relaxed)
+    }
+
+    /// Returns the statistics of the meter, if enabled on it.
+    pub fn stats(&self) -> Option<MeterStats> {
+        self.stats.get().map(|stats| stats.lock().unwrap().snapshot())
+    }
+}
+
+impl fmt::Debug for MeterHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MeterHandle").field("counter", &self.get_counter()).finish()
+    }
+}
+
+// statistics are only collected once enabled, and are shared with the handles
+type SharedStats = Arc<OnceLock<Mutex<StatsRecorder>>>;
+
+fn enable_stats(stats: &SharedStats, clock: Box<dyn Clock>) {
+    let recorder = StatsRecorder::new(clock);
+    match stats.get() {
+        Some(stats) => *stats.lock().unwrap() = recorder,
+        None => {
+            let _ = stats.set(Mutex::new(recorder));
+        }
+    }
+}
+
+fn record_stats(stats: &SharedStats, size: usize) {
+    if let Some(stats) = stats.get() {
+        stats.lock().unwrap().record(size);
+    }
+}
+
+/// SeekPolicy selects what seeking a MeteringReader does to its count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekPolicy {
+    /// The count is left alone and keeps counting bytes read, this is the default.
+    Keep,
+    /// The count is reset to 0 on every seek.
+    Reset,
+    /// The count moves along with the position, so a seek back by 10 bytes takes 10 from the count.
+    /// The count does not go below 0.
+    Adjust,
 }
 
-/// MeteringReader wraps around a reader and atomically accumulates the total count of bytes written to it.
+/// MeteringReader wraps around a reader and atomically accumulates the total count of bytes read from it.
 ///
-/// Use as_reader() to obtain a io::Reader handle to it.
-pub struct MeteringReader<'a> {
-    inner: MeteringReaderHandle<'a>,
+/// BufRead and Seek of the underlying reader are passed through, bytes passed to consume() are counted
+/// and seeking applies the SeekPolicy.
+pub struct MeteringReader<R> {
+    underlying_reader: R,
     counter: Arc<AtomicUsize>,
+    stats: SharedStats,
+    seek_policy: SeekPolicy,
+    progress: Option<ProgressReporter>,
+    limit: Option<usize>,
+    // the byte read past the limit, served once the limit allows it
+    stash: Option<u8>,
 }
 
-impl MeteringReader<'_> {
-    pub fn new(r: &mut dyn Read) -> MeteringReader {
-        let counter = Arc::new(AtomicUsize::new(0));
+impl<R: Read> MeteringReader<R> {
+    pub fn new(r: R) -> MeteringReader<R> {
         MeteringReader{
-            inner : MeteringReaderHandle{
-                underlying_reader: r,
-                counter: Arc::clone(&counter),
-            },
-            counter: Arc::clone(&counter),
+            underlying_reader: r,
+            counter: Arc::new(AtomicUsize::new(0)),
+            stats: Arc::new(OnceLock::new()),
+            seek_policy: SeekPolicy::Keep,
+            progress: None,
+            limit: None,
+            stash: None,
         }
     }
 
+    /// Returns this reader as a io::Read trait object.
     pub fn as_reader(&mut self) -> &mut dyn Read {
-        &mut self.inner
+        self
     }
 
     pub fn get_counter(&self) -> usize {
         self.counter.load(Ordering::Relaxed)
     }
+
+    /// Returns a handle to the counter which can be polled while the reader is in use.
+    pub fn handle(&self) -> MeterHandle {
+        MeterHandle{ counter: Arc::clone(&self.counter), stats: Arc::clone(&self.stats) }
+    }
+
+    /// Starts collecting statistics on read calls timed by `clock`, enabling again starts over.
+    pub fn enable_stats<C: Clock + 'static>(&mut self, clock: C) {
+        enable_stats(&self.stats, Box::new(clock))
+    }
+
+    /// Returns the statistics collected since enable_stats(), None if not enabled.
+    pub fn stats(&self) -> Option<MeterStats> {
+        self.handle().stats()
+    }
+
+    /// Attaches a reporter called back with the progress of this reader, replacing any previous one.
+    ///
+    /// The reporter starts timing now and reports the count of this reader, including bytes read before.
+    pub fn set_progress(&mut self, mut reporter: ProgressReporter) {
+        reporter.attach(self.get_counter());
+        self.progress = Some(reporter);
+    }
+
+    /// Detaches the progress reporter, if any.
+    pub fn clear_progress(&mut self) {
+        self.progress = None;
+    }
+
+    /// Sets the most bytes this reader lets through, reading past it fails with LimitExceeded. None lifts the limit.
+    ///
+    /// The limit applies to the count, so resetting or adjusting it by seeking frees up quota.
+    /// The byte read to find the limit exceeded is held back, and served first once the limit allows it.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_seek_policy(&mut self, policy: SeekPolicy) {
+        self.seek_policy = policy;
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.underlying_reader
+    }
+
+    /// Gets a mutable reference to the underlying reader, reading from it directly is not counted.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.underlying_reader
+    }
+
+    /// Unwraps this reader, returning the underlying reader. Handles keep the final count.
+    /// A byte held back at the limit is lost.
+    pub fn into_inner(self) -> R {
+        self.underlying_reader
+    }
 }
 
-impl Read for MeteringReaderHandle<'_> {
+impl<R: Read> Read for MeteringReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        match self.underlying_reader.read(buf) {
-            Ok(size) => {
-                self.counter.fetch_add(size, Ordering::Relaxed);
-                Ok(size)
+        let count = self.get_counter();
+        let size = match self.limit {
+            _ if buf.is_empty() => self.underlying_reader.read(buf)?,
+            Some(limit) if count >= limit => {
+                // one byte past the limit is enough to tell it is exceeded
+                if self.stash.is_none() {
+                    let mut byte = [0u8];
+                    if self.underlying_reader.read(&mut byte)? == 1 {
+                        self.stash = Some(byte[0]);
+                    }
+                }
+                if self.stash.is_some() {
+                    return Err(io::Error::other(LimitExceeded { limit, consumed: count }));
+                }
+                0
+            }
+            _ => match self.stash.take() {
+                Some(byte) => {
+                    buf[0] = byte;
+                    1
+                }
+                None => {
+                    let len = self.limit.map_or(buf.len(), |limit| buf.len().min(limit - count));
+                    self.underlying_reader.read(&mut buf[..len])?
+                }
             },
-            Err(e) => {
-                Err(e)
+        };
+        let count = self.counter.fetch_add(size, Ordering::Relaxed) + size;
+        record_stats(&self.stats, size);
+        if let Some(ref mut progress) = self.progress {
+            progress.update(count, size == 0 && !buf.is_empty());
+        }
+        Ok(size)
+    }
+}
+
+impl<R: BufRead> BufRead for MeteringReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8], io::Error> {
+        let count = self.get_counter();
+        match self.limit {
+            Some(limit) if count >= limit => {
+                // there is data past the limit, nothing of it is served
+                if self.stash.is_some() || !self.underlying_reader.fill_buf()?.is_empty() {
+                    return Err(io::Error::other(LimitExceeded { limit, consumed: count }));
+                }
+                Ok(&[])
+            }
+            _ => {
+                if let Some(ref byte) = self.stash {
+                    return Ok(std::slice::from_ref(byte));
+                }
+                let buf = self.underlying_reader.fill_buf()?;
+                Ok(&buf[..self.limit.map_or(buf.len(), |limit| buf.len().min(limit - count))])
             }
         }
     }
+
+    fn consume(&mut self, amt: usize) {
+        match self.stash {
+            // fill_buf() served the held back byte alone
+            Some(_) if amt > 0 => self.stash = None,
+            _ => self.underlying_reader.consume(amt),
+        }
+        let count = self.counter.fetch_add(amt, Ordering::Relaxed) + amt;
+        record_stats(&self.stats, amt);
+        if let Some(ref mut progress) = self.progress {
+            progress.update(count, false);
+        }
+    }
+}
+
+impl<R: Read + Seek> Seek for MeteringReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+        // the underlying reader is one byte ahead of a held back byte, which is dropped
+        let pos = match pos {
+            SeekFrom::Current(delta) if self.stash.is_some() => SeekFrom::Current(delta - 1),
+            pos => pos,
+        };
+        self.stash = None;
+        match self.seek_policy {
+            SeekPolicy::Keep => self.underlying_reader.seek(pos),
+            SeekPolicy::Reset => {
+                let new_pos = self.underlying_reader.seek(pos)?;
+                self.counter.store(0, Ordering::Relaxed);
+                Ok(new_pos)
+            }
+            SeekPolicy::Adjust => {
+                let old_pos = self.underlying_reader.stream_position()?;
+                let new_pos = self.underlying_reader.seek(pos)?;
+                let count = self.counter.load(Ordering::Relaxed) as u64;
+                let count = (count + new_pos).saturating_sub(old_pos);
+                self.counter.store(count as usize, Ordering::Relaxed);
+                Ok(new_pos)
+            }
+        }
+    }
+}
+
+/// LimitExceeded is the error a MeteringReader fails with once reading goes past its limit.
+///
+/// Returned inside an io::Error of kind Other, see `downcast_ref::<LimitExceeded>()`.
+/// Reading on keeps failing until the limit is raised or the count goes down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    /// The limit of the reader.
+    pub limit: usize,
+    /// The count of the reader when the limit was found exceeded, the bytes past the limit are not counted.
+    pub consumed: usize,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read limit of {} bytes exceeded, {} bytes consumed", self.limit, self.consumed)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// MeteringWriter wraps around a writer and atomically accumulates the total count of bytes accepted by it,
+/// as well as the count of successful flushes.
+pub struct MeteringWriter<W> {
+    underlying_writer: W,
+    counter: Arc<AtomicUsize>,
+    flush_counter: Arc<AtomicUsize>,
+    stats: SharedStats,
+}
+
+impl<W: Write> MeteringWriter<W> {
+    pub fn new(w: W) -> MeteringWriter<W> {
+        MeteringWriter{
+            underlying_writer: w,
+            counter: Arc::new(AtomicUsize::new(0)),
+            flush_counter: Arc::new(AtomicUsize::new(0)),
+            stats: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Returns this writer as a io::Write trait object.
+    pub fn as_writer(&mut self) -> &mut dyn Write {
+        self
+    }
+
+    pub fn get_counter(&self) -> usize {
+        self.counter.load(Ordering::Relaxed)
+    }
+
+    pub fn get_flush_counter(&self) -> usize {
+        self.flush_counter.load(Ordering::Relaxed)
+    }
+
+    /// Returns a handle to the byte counter which can be polled while the writer is in use.
+    pub fn handle(&self) -> MeterHandle {
+        MeterHandle{ counter: Arc::clone(&self.counter), stats: Arc::clone(&self.stats) }
+    }
+
+    /// Starts collecting statistics on write calls timed by `clock`, enabling again starts over.
+    pub fn enable_stats<C: Clock + 'static>(&mut self, clock: C) {
+        enable_stats(&self.stats, Box::new(clock))
+    }
+
+    /// Returns the statistics collected since enable_stats(), None if not enabled.
+    pub fn stats(&self) -> Option<MeterStats> {
+        self.handle().stats()
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.underlying_writer
+    }
+
+    /// Gets a mutable reference to the underlying writer, writing to it directly is not counted.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.underlying_writer
+    }
+
+    /// Unwraps this writer, returning the underlying writer. Handles keep the final count.
+    pub fn into_inner(self) -> W {
+        self.underlying_writer
+    }
+}
+
+impl<W: Write> Write for MeteringWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.underlying_writer.write(buf)?;
+        self.counter.fetch_add(size, Ordering::Relaxed);
+        record_stats(&self.stats, size);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.underlying_writer.flush()?;
+        self.flush_counter.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate as lib;
-    use std::io::{self, Read};
+    use std::io::{self, Read, Write};
 
+    #[allow(unused_parens)]
     pub struct SlowReader<'a> {
         underlying_reader: &'a mut dyn (Read),
     }
@@ -105,7 +649,109 @@ mod tests {
         }
     }
 
+    // ScriptedReader plays back a list of results, then EOF
+    pub struct ScriptedReader {
+        pub steps: std::collections::VecDeque<Result<&'static [u8], io::ErrorKind>>,
+    }
+
+    impl ScriptedReader {
+        pub fn new(steps: Vec<Result<&'static [u8], io::ErrorKind>>) -> ScriptedReader {
+            ScriptedReader { steps: steps.into() }
+        }
+    }
+
+    impl Read for ScriptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+            match self.steps.pop_front() {
+                Some(Ok(data)) => {
+                    let size = data.len().min(buf.len());
+                    buf[..size].copy_from_slice(&data[..size]);
+                    if size < data.len() {
+                        self.steps.push_front(Ok(&data[size..]));
+                    }
+                    Ok(size)
+                }
+                Some(Err(kind)) => Err(io::Error::new(kind, "scripted")),
+                None => Ok(0),
+            }
+        }
+    }
+
+    // TestWriter keeps the bytes it accepts, at most `chunk` per write and `capacity` in total.
+    // Write calls return the scripted errors in order first, a full writer fails with the `full` error kind,
+    // or accepts 0 bytes if there is none. A broken writer fails every write and flush.
+    pub struct TestWriter {
+        pub data: Vec<u8>,
+        chunk: usize,
+        capacity: usize,
+        full: Option<io::ErrorKind>,
+        errors: std::collections::VecDeque<Option<io::ErrorKind>>,
+        broken: Option<io::ErrorKind>,
+    }
+
+    impl TestWriter {
+        pub fn new() -> TestWriter {
+            TestWriter {
+                data: Vec::new(),
+                chunk: usize::MAX,
+                capacity: usize::MAX,
+                full: None,
+                errors: std::collections::VecDeque::new(),
+                broken: None,
+            }
+        }
+
+        pub fn chunk(mut self, chunk: usize) -> TestWriter {
+            self.chunk = chunk;
+            self
+        }
+
+        pub fn capacity(mut self, capacity: usize, full: Option<io::ErrorKind>) -> TestWriter {
+            self.capacity = capacity;
+            self.full = full;
+            self
+        }
+
+        pub fn errors(mut self, errors: Vec<Option<io::ErrorKind>>) -> TestWriter {
+            self.errors = errors.into();
+            self
+        }
+
+        pub fn broken(mut self, kind: io::ErrorKind) -> TestWriter {
+            self.broken = Some(kind);
+            self
+        }
+    }
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(kind) = self.broken {
+                return Err(io::Error::new(kind, "broken"));
+            }
+            if let Some(Some(kind)) = self.errors.pop_front() {
+                return Err(io::Error::new(kind, "scripted"));
+            }
+            let room = self.capacity - self.data.len();
+            if let (0, Some(kind)) = (room, self.full) {
+                return Err(io::Error::new(kind, "full"));
+            }
+            let size = buf.len().min(self.chunk).min(room);
+            self.data.extend_from_slice(&buf[..size]);
+            Ok(size)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            match self.broken {
+                Some(kind) => Err(io::Error::new(kind, "broken")),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[allow(clippy::useless_vec)]
     mod test_read_full {
+        use std::io;
+        use super::ScriptedReader;
+        use crate::PartialReadError;
 
         #[test]
         fn test_read_full_slow() {
@@ -132,8 +778,213 @@ mod tests {
             let res = super::lib::read_full(&mut buf[..], &mut underlying_data);
             assert_eq!(res.unwrap(), 8usize);
         }
+
+        #[test]
+        fn test_read_full_interrupted() {
+            let mut reader = ScriptedReader::new(vec![
+                Ok(b"ab"), Err(io::ErrorKind::Interrupted), Ok(b"cd"), Err(io::ErrorKind::Interrupted), Ok(b"ef"),
+            ]);
+            let mut buf = [0u8; 6];
+            assert_eq!(super::lib::read_full(&mut buf, &mut reader).unwrap(), 6);
+            assert_eq!(&buf, b"abcdef");
+        }
+
+        #[test]
+        fn test_read_full_partial_error() {
+            let mut reader = ScriptedReader::new(vec![Ok(b"abc"), Err(io::ErrorKind::ConnectionReset), Ok(b"def")]);
+            let mut buf = [0u8; 6];
+            let err = super::lib::read_full(&mut buf, &mut reader).unwrap_err();
+            assert_eq!(err.bytes_read, 3);
+            assert_eq!(err.source.kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(&buf[..3], b"abc");
+
+            // resume where it failed
+            assert_eq!(super::lib::read_full(&mut buf[3..], &mut reader).unwrap(), 3);
+            assert_eq!(&buf, b"abcdef");
+
+            let err: io::Error = PartialReadError { bytes_read: 3, source: io::ErrorKind::TimedOut.into() }.into();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            let inner = err.get_ref().unwrap().downcast_ref::<PartialReadError>().unwrap();
+            assert_eq!(inner.bytes_read, 3);
+        }
+
+        #[test]
+        fn test_read_full_dyn() {
+            let mut underlying_data: &[u8] = &[0, 1, 2];
+            let reader: &mut dyn io::Read = &mut underlying_data;
+            let mut buf = [0u8; 2];
+            assert_eq!(super::lib::read_full(&mut buf, reader).unwrap(), 2);
+        }
+    }
+
+    mod test_read_at_least {
+        use std::io;
+        use super::ScriptedReader;
+        use crate::read_at_least;
+
+        #[test]
+        fn test_read_at_least() {
+            let mut reader = ScriptedReader::new(vec![Ok(b"ab"), Err(io::ErrorKind::Interrupted), Ok(b"cd"), Ok(b"efgh")]);
+            let mut buf = [0u8; 8];
+            // stops as soon as min is reached, without filling the buffer
+            assert_eq!(read_at_least(&mut buf, 3, &mut reader).unwrap(), 4);
+            assert_eq!(&buf[..4], b"abcd");
+            assert_eq!(read_at_least(&mut buf, 0, &mut reader).unwrap(), 0);
+            assert_eq!(read_at_least(&mut buf, 1, &mut reader).unwrap(), 4);
+        }
+
+        #[test]
+        fn test_read_at_least_eof() {
+            let mut reader: &[u8] = b"abc";
+            let mut buf = [0u8; 8];
+            let err = read_at_least(&mut buf, 5, &mut reader).unwrap_err();
+            assert_eq!(err.source.kind(), io::ErrorKind::UnexpectedEof);
+            assert_eq!(err.bytes_read, 3);
+            let err = read_at_least(&mut buf, 1, &mut reader).unwrap_err();
+            assert_eq!(err.bytes_read, 0);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_read_at_least_short_buffer() {
+            let mut buf = [0u8; 2];
+            let _ = read_at_least(&mut buf, 3, &mut io::empty());
+        }
+    }
+
+    mod test_copy_n {
+        use std::io::{self, Read};
+        use super::{ScriptedReader, TestWriter};
+        use crate::copy_n;
+
+        #[test]
+        fn test_copy_n() {
+            let mut reader = io::repeat(7).take(20000);
+            let mut out = Vec::new();
+            assert_eq!(copy_n(&mut reader, &mut out, 10000).unwrap(), 10000);
+            assert_eq!(out.len(), 10000);
+            assert_eq!(reader.limit(), 10000);
+            assert_eq!(copy_n(&mut reader, &mut out, 0).unwrap(), 0);
+        }
+
+        #[test]
+        fn test_copy_n_eof() {
+            let mut reader = ScriptedReader::new(vec![Ok(b"abc"), Err(io::ErrorKind::Interrupted), Ok(b"de")]);
+            let mut out = Vec::new();
+            let err = copy_n(&mut reader, &mut out, 8).unwrap_err();
+            assert_eq!(err.source.kind(), io::ErrorKind::UnexpectedEof);
+            assert_eq!(err.bytes_copied, 5);
+            assert_eq!(out, b"abcde");
+        }
+
+        #[test]
+        fn test_copy_n_write_errors() {
+            let mut reader: &[u8] = b"0123456789";
+            let mut writer = TestWriter::new().chunk(3).capacity(7, None);
+            let err = copy_n(&mut reader, &mut writer, 10).unwrap_err();
+            assert_eq!(err.source.kind(), io::ErrorKind::WriteZero);
+            assert_eq!(err.bytes_copied, 7);
+            assert_eq!(writer.data, b"0123456");
+
+            let mut reader = ScriptedReader::new(vec![Ok(b"ab"), Err(io::ErrorKind::BrokenPipe)]);
+            let err: io::Error = copy_n(&mut reader, &mut io::sink(), 4).unwrap_err().into();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        }
     }
 
+    mod test_write_full {
+        use std::io;
+        use super::TestWriter;
+        use crate::write_full;
+
+        #[test]
+        fn test_write_full() {
+            let mut writer = TestWriter::new().chunk(3).errors(vec![None, Some(io::ErrorKind::Interrupted)]);
+            assert_eq!(write_full(b"0123456789", &mut writer).unwrap(), 10);
+            assert_eq!(writer.data, b"0123456789");
+            assert_eq!(write_full(b"", &mut writer).unwrap(), 0);
+        }
+
+        #[test]
+        fn test_write_full_resume() {
+            let mut writer = TestWriter::new().chunk(4).errors(vec![None, Some(io::ErrorKind::TimedOut)]);
+            let buf = b"0123456789";
+            let err = write_full(buf, &mut writer).unwrap_err();
+            assert_eq!(err.source.kind(), io::ErrorKind::TimedOut);
+            assert_eq!(err.bytes_written, 4);
+            assert_eq!(write_full(&buf[err.bytes_written..], &mut writer).unwrap(), 6);
+            assert_eq!(writer.data, b"0123456789");
+        }
+
+        #[test]
+        fn test_write_zero() {
+            let mut buf = [0u8; 5];
+            let mut writer = &mut buf[..];
+            let err = write_full(b"0123456789", &mut writer).unwrap_err();
+            assert_eq!(err.source.kind(), io::ErrorKind::WriteZero);
+            assert_eq!(err.bytes_written, 5);
+            let err: io::Error = err.into();
+            assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        }
+    }
+
+    mod test_sources {
+        use std::io::Read;
+        use crate::{copy_n, BlackHole, Random, Repeat, Zero};
+
+        #[test]
+        fn test_zero() {
+            let mut buf = [1u8; 100];
+            assert_eq!(Zero{}.read(&mut buf).unwrap(), 100);
+            assert!(buf.iter().all(|&b| b == 0));
+            assert_eq!(copy_n(&mut Zero{}, &mut BlackHole{}, 1 << 20).unwrap(), 1 << 20);
+        }
+
+        #[test]
+        fn test_repeat() {
+            let mut repeat = Repeat::new(b"abc");
+            let mut buf = [0u8; 4];
+            repeat.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"abca");
+            repeat.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"bcab");
+            let mut out = Vec::new();
+            repeat.take(7).read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"cabcabc");
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_repeat_empty() {
+            Repeat::new(b"");
+        }
+
+        #[test]
+        fn test_random() {
+            let mut whole = vec![0u8; 1000];
+            Random::new(42).read_exact(&mut whole).unwrap();
+
+            // the stream does not depend on read sizes
+            let mut random = Random::new(42);
+            let mut pieces = Vec::new();
+            for size in [1, 3, 8, 13, 975] {
+                let mut buf = vec![0u8; size];
+                random.read_exact(&mut buf).unwrap();
+                pieces.extend_from_slice(&buf);
+            }
+            assert_eq!(pieces, whole);
+
+            let mut other = vec![0u8; 1000];
+            Random::new(43).read_exact(&mut other).unwrap();
+            assert_ne!(other, whole);
+
+            // roughly uniform
+            let ones: u32 = whole.iter().map(|b| b.count_ones()).sum();
+            assert!((3600..4400).contains(&ones), "{}", ones);
+        }
+    }
+
+    #[allow(clippy::bool_assert_comparison)]
     mod test_metering_reader {
         use std::io;
         use crate::{MeteringReader, BlackHole};
@@ -166,4 +1017,511 @@ mod tests {
             assert_eq!(counter_ref.upgrade().is_none(), true);
         }
     }
+
+    mod test_owned_metering {
+        use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+        use crate::{MeteringReader, MeteringWriter, SeekPolicy, BlackHole};
+
+        #[test]
+        fn test_owned_reader() {
+            let mut meter = MeteringReader::new(Cursor::new(b"0123456789".to_vec()));
+            let handle = meter.handle();
+            let mut buf = [0u8; 4];
+            meter.read_exact(&mut buf).unwrap();
+            assert_eq!(meter.get_ref().position(), 4);
+            let inner = meter.into_inner();
+            assert_eq!(inner.position(), 4);
+            assert_eq!(handle.get_counter(), 4);
+        }
+
+        #[test]
+        fn test_buf_read_counts_consumed() {
+            let input = "first line\nsecond\nthird\n";
+            let mut meter = MeteringReader::new(BufReader::new(input.as_bytes()));
+            let mut line = String::new();
+            meter.read_line(&mut line).unwrap();
+            assert_eq!(meter.get_counter(), 11);
+            assert_eq!(meter.fill_buf().unwrap(), b"second\nthird\n");
+            assert_eq!(meter.get_counter(), 11);
+            meter.consume(7);
+            assert_eq!(meter.get_counter(), 18);
+            assert_eq!(meter.lines().count(), 1);
+        }
+
+        #[test]
+        fn test_seek_policies() {
+            let data = vec![0u8; 100];
+
+            let mut meter = MeteringReader::new(Cursor::new(data.clone()));
+            io::copy(&mut (&mut meter).take(40), &mut BlackHole{}).unwrap();
+            meter.seek(SeekFrom::Start(10)).unwrap();
+            io::copy(&mut (&mut meter).take(5), &mut BlackHole{}).unwrap();
+            assert_eq!(meter.get_counter(), 45);
+
+            let mut meter = MeteringReader::new(Cursor::new(data.clone()));
+            meter.set_seek_policy(SeekPolicy::Reset);
+            io::copy(&mut (&mut meter).take(40), &mut BlackHole{}).unwrap();
+            meter.seek(SeekFrom::Start(10)).unwrap();
+            io::copy(&mut (&mut meter).take(5), &mut BlackHole{}).unwrap();
+            assert_eq!(meter.get_counter(), 5);
+
+            let mut meter = MeteringReader::new(Cursor::new(data));
+            meter.set_seek_policy(SeekPolicy::Adjust);
+            io::copy(&mut (&mut meter).take(40), &mut BlackHole{}).unwrap();
+            assert_eq!(meter.seek(SeekFrom::Current(-30)).unwrap(), 10);
+            assert_eq!(meter.get_counter(), 10);
+            meter.seek(SeekFrom::End(-20)).unwrap();
+            assert_eq!(meter.get_counter(), 80);
+            meter.seek(SeekFrom::Start(0)).unwrap();
+            assert_eq!(meter.get_counter(), 0);
+        }
+
+        #[test]
+        fn test_owned_writer() {
+            let mut meter = MeteringWriter::new(Vec::new());
+            meter.write_all(b"12345").unwrap();
+            meter.flush().unwrap();
+            assert_eq!(meter.get_ref(), b"12345");
+            assert_eq!(meter.get_counter(), 5);
+            assert_eq!(meter.get_flush_counter(), 1);
+            assert_eq!(meter.into_inner(), b"12345");
+        }
+
+        #[test]
+        fn test_boxed_into_thread() {
+            let mut meter = MeteringReader::new(Cursor::new(vec![1u8; 1000]));
+            let handle = meter.handle();
+            let copied = std::thread::spawn(move || io::copy(&mut meter, &mut BlackHole{}).unwrap()).join().unwrap();
+            assert_eq!(copied, 1000);
+            assert_eq!(handle.get_counter(), 1000);
+        }
+    }
+
+    mod test_meter_stats {
+        use std::io::{self, BufRead, Read, Write};
+        use std::time::Duration;
+        use crate::clock::ManualClock;
+        use crate::{MeteringReader, MeteringWriter, BlackHole};
+
+        #[test]
+        fn test_stats_disabled_by_default() {
+            let meter = MeteringReader::new(io::empty());
+            assert!(meter.stats().is_none());
+            assert!(meter.handle().stats().is_none());
+        }
+
+        #[test]
+        fn test_reader_stats() {
+            let clock = ManualClock::new();
+            let input = vec![0u8; 10000];
+            let mut meter = MeteringReader::new(&input[..]);
+            let handle = meter.handle();
+            meter.enable_stats(clock.clone());
+
+            let mut buf = [0u8; 1000];
+            for _ in 0..5 {
+                clock.advance(Duration::from_millis(100));
+                meter.read_exact(&mut buf).unwrap();
+            }
+            clock.advance(Duration::from_millis(500));
+            let stats = handle.stats().unwrap();
+            assert_eq!(stats.bytes, 5000);
+            assert_eq!(stats.calls, 5);
+            assert_eq!(stats.elapsed, Duration::from_secs(1));
+            assert_eq!(stats.average_rate, 5000.0);
+            assert_eq!(stats.instant_rate, 10000.0);
+            // 1000 bytes every 100ms and then nothing for 500ms
+            assert!(stats.moving_rate > 8000.0 && stats.moving_rate < 10000.0, "{}", stats.moving_rate);
+            // 1000 bytes fall in 512..1024
+            assert_eq!(stats.size_histogram[10], 5);
+            assert_eq!(stats.size_histogram.iter().sum::<usize>(), 5);
+        }
+
+        #[test]
+        fn test_moving_rate_follows_changes() {
+            let clock = ManualClock::new();
+            let mut meter = MeteringWriter::new(BlackHole{});
+            meter.enable_stats(clock.clone());
+            for _ in 0..100 {
+                clock.advance(Duration::from_secs(1));
+                meter.write_all(&[0u8; 100]).unwrap();
+            }
+            let fast = meter.stats().unwrap().moving_rate;
+            assert!((fast - 100.0).abs() < 0.01, "{}", fast);
+            for _ in 0..100 {
+                clock.advance(Duration::from_secs(1));
+                meter.write_all(&[0u8; 10]).unwrap();
+            }
+            let slow = meter.stats().unwrap().moving_rate;
+            assert!((slow - 10.0).abs() < 0.01, "{}", slow);
+            clock.advance(Duration::from_secs(60));
+            assert!(meter.stats().unwrap().moving_rate < 0.01);
+            assert_eq!(meter.stats().unwrap().average_rate, 11000.0 / 260.0);
+        }
+
+        #[test]
+        fn test_calls_in_the_same_instant() {
+            let clock = ManualClock::new();
+            let mut meter = MeteringReader::new(io::repeat(1).take(100));
+            meter.enable_stats(clock.clone());
+            let mut buf = [0u8; 10];
+            meter.read_exact(&mut buf).unwrap();
+            meter.read_exact(&mut buf).unwrap();
+            let stats = meter.stats().unwrap();
+            assert_eq!(stats.calls, 2);
+            assert_eq!(stats.instant_rate, 0.0);
+            assert_eq!(stats.moving_rate, 0.0);
+            clock.advance(Duration::from_secs(2));
+            meter.read_exact(&mut buf).unwrap();
+            assert_eq!(meter.stats().unwrap().instant_rate, 15.0);
+        }
+
+        #[test]
+        fn test_consume_is_a_call() {
+            let clock = ManualClock::new();
+            let mut meter = MeteringReader::new(&b"line\nline\n"[..]);
+            meter.enable_stats(clock);
+            let mut line = String::new();
+            meter.read_line(&mut line).unwrap();
+            let stats = meter.stats().unwrap();
+            assert_eq!((stats.bytes, stats.calls), (5, 1));
+            assert_eq!(stats.size_histogram[3], 1);
+        }
+    }
+
+    mod test_progress {
+        use std::io::{self, Read};
+        use std::sync::mpsc;
+        use std::time::Duration;
+        use crate::clock::ManualClock;
+        use crate::progress::{Progress, ProgressReporter};
+        use crate::MeteringReader;
+
+        fn collect(reporter: impl FnOnce(ProgressReporter) -> ProgressReporter) -> (ProgressReporter, mpsc::Receiver<Progress>) {
+            let (tx, rx) = mpsc::channel();
+            let reporter = reporter(ProgressReporter::new(move |p: &Progress| tx.send(p.clone()).unwrap()));
+            (reporter, rx)
+        }
+
+        #[test]
+        fn test_every_bytes() {
+            let (reporter, rx) = collect(|r| r.every_bytes(250).expected_total(1000));
+            let mut meter = MeteringReader::new(io::repeat(0).take(1000));
+            meter.set_progress(reporter);
+            let mut buf = [0u8; 100];
+            while meter.read(&mut buf).unwrap() > 0 {}
+            let reports: Vec<Progress> = rx.try_iter().collect();
+            let counts: Vec<usize> = reports.iter().map(|p| p.bytes).collect();
+            assert_eq!(counts, vec![300, 600, 900, 1000]);
+            assert_eq!(reports[1].percentage, Some(60.0));
+            assert_eq!(reports[3].percentage, Some(100.0));
+            assert_eq!(reports[3].eta, Some(Duration::from_secs(0)));
+            assert_eq!(reports[3].total, Some(1000));
+        }
+
+        #[test]
+        fn test_every_duration_and_eta() {
+            let clock = ManualClock::new();
+            let (reporter, rx) = collect(|r| r.every(Duration::from_secs(1)).expected_total(1000).clock(clock.clone()));
+            let mut meter = MeteringReader::new(io::repeat(0).take(1000));
+            meter.set_progress(reporter);
+            let mut buf = [0u8; 100];
+            for _ in 0..4 {
+                clock.advance(Duration::from_millis(500));
+                meter.read_exact(&mut buf).unwrap();
+            }
+            let reports: Vec<Progress> = rx.try_iter().collect();
+            assert_eq!(reports.len(), 2);
+            assert_eq!(reports[0].bytes, 200);
+            assert_eq!(reports[0].elapsed, Duration::from_secs(1));
+            // 200 bytes per second, 800 bytes left
+            assert_eq!(reports[0].eta, Some(Duration::from_secs(4)));
+            assert_eq!(reports[1].bytes, 400);
+            assert_eq!(reports[1].eta, Some(Duration::from_secs(3)));
+        }
+
+        #[test]
+        fn test_eta_out_of_range() {
+            let clock = ManualClock::new();
+            let (reporter, rx) = collect(|r| r.expected_total(usize::MAX).clock(clock.clone()));
+            let mut meter = MeteringReader::new(io::repeat(0));
+            meter.set_progress(reporter);
+            clock.advance(Duration::from_secs(10));
+            meter.read_exact(&mut [0u8; 1]).unwrap();
+            let report = rx.try_recv().unwrap();
+            assert_eq!(report.bytes, 1);
+            assert_eq!(report.eta, None);
+        }
+
+        #[test]
+        fn test_unknown_total() {
+            let (reporter, rx) = collect(|r| r);
+            let mut meter = MeteringReader::new(&b"abcd"[..]);
+            meter.set_progress(reporter);
+            let mut buf = [0u8; 3];
+            meter.read_exact(&mut buf).unwrap();
+            let report = rx.try_recv().unwrap();
+            assert_eq!(report.bytes, 3);
+            assert_eq!(report.percentage, None);
+            assert_eq!(report.eta, None);
+        }
+
+        #[test]
+        fn test_attach_midway_and_clear() {
+            let clock = ManualClock::new();
+            let (reporter, rx) = collect(|r| r.every_bytes(10).expected_total(40).clock(clock.clone()));
+            let mut meter = MeteringReader::new(io::repeat(0).take(40));
+            let mut buf = [0u8; 10];
+            meter.read_exact(&mut buf).unwrap();
+            meter.set_progress(reporter);
+            clock.advance(Duration::from_secs(1));
+            meter.read_exact(&mut buf).unwrap();
+            let report = rx.try_recv().unwrap();
+            assert_eq!(report.bytes, 20);
+            // the 10 bytes before attaching do not count into the rate
+            assert_eq!(report.eta, Some(Duration::from_secs(2)));
+            meter.clear_progress();
+            meter.read_exact(&mut buf).unwrap();
+            assert!(rx.try_recv().is_err());
+        }
+    }
+
+    mod test_limit {
+        use std::io::{self, BufRead, Read, Seek, SeekFrom};
+        use std::sync::mpsc;
+        use crate::clock::ManualClock;
+        use crate::progress::{Progress, ProgressReporter};
+        use crate::{LimitExceeded, MeteringReader, SeekPolicy};
+
+        fn limit_error(e: &io::Error) -> LimitExceeded {
+            e.get_ref().and_then(|e| e.downcast_ref::<LimitExceeded>()).unwrap().clone()
+        }
+
+        #[test]
+        fn test_within_limit() {
+            let mut meter = MeteringReader::new(&b"0123456789"[..]);
+            meter.set_limit(Some(10));
+            let mut out = Vec::new();
+            meter.read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"0123456789");
+        }
+
+        #[test]
+        fn test_over_limit() {
+            let mut meter = MeteringReader::new(io::repeat(1));
+            meter.set_limit(Some(100));
+            let mut out = Vec::new();
+            let err = meter.read_to_end(&mut out).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            assert_eq!(limit_error(&err), LimitExceeded { limit: 100, consumed: 100 });
+            assert_eq!(out.len(), 100);
+            assert_eq!(meter.get_counter(), 100);
+
+            // it keeps failing without reading on
+            let mut buf = [0u8; 10];
+            assert_eq!(limit_error(&meter.read(&mut buf).unwrap_err()).consumed, 100);
+            assert_eq!(meter.get_counter(), 100);
+
+            meter.set_limit(None);
+            assert_eq!(meter.read(&mut buf).unwrap(), 1);
+            assert_eq!(meter.read(&mut buf).unwrap(), 10);
+            assert_eq!(meter.get_counter(), 111);
+        }
+
+        #[test]
+        fn test_raised_limit_loses_nothing() {
+            let mut meter = MeteringReader::new(&b"0123456789ABC"[..]);
+            meter.set_limit(Some(5));
+            let mut out = Vec::new();
+            assert!(meter.read_to_end(&mut out).is_err());
+            assert_eq!(out, b"01234");
+            meter.set_limit(Some(8));
+            let mut line = String::new();
+            assert!(meter.read_line(&mut line).is_err());
+            assert_eq!(line, "567");
+            meter.set_limit(None);
+            out.clear();
+            meter.read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"89ABC");
+            assert_eq!(meter.get_counter(), 13);
+        }
+
+        #[test]
+        fn test_max_limit() {
+            let mut meter = MeteringReader::new(&b"abc"[..]);
+            meter.set_limit(Some(usize::MAX));
+            let mut out = Vec::new();
+            meter.read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"abc");
+            let mut line = String::new();
+            let mut meter = MeteringReader::new(&b"abc"[..]);
+            meter.set_limit(Some(usize::MAX));
+            meter.read_line(&mut line).unwrap();
+            assert_eq!(line, "abc");
+        }
+
+        #[test]
+        fn test_over_limit_not_reported() {
+            let clock = ManualClock::new();
+            let (tx, rx) = mpsc::channel();
+            let mut meter = MeteringReader::new(io::repeat(1));
+            meter.set_limit(Some(10));
+            meter.enable_stats(clock.clone());
+            meter.set_progress(ProgressReporter::new(move |p: &Progress| tx.send(p.bytes).unwrap()));
+            let mut buf = [0u8; 8];
+            assert_eq!(meter.read(&mut buf).unwrap(), 8);
+            assert_eq!(meter.read(&mut buf).unwrap(), 2);
+            assert!(meter.read(&mut buf).is_err());
+            assert_eq!(meter.get_counter(), 10);
+            let stats = meter.stats().unwrap();
+            assert_eq!((stats.bytes, stats.calls), (10, 2));
+            assert_eq!(rx.try_iter().collect::<Vec<usize>>(), vec![8, 10]);
+        }
+
+        #[test]
+        fn test_buf_read_over_limit() {
+            let mut meter = MeteringReader::new(&b"line one\nline two\n"[..]);
+            meter.set_limit(Some(12));
+            let mut line = String::new();
+            meter.read_line(&mut line).unwrap();
+            assert_eq!(line, "line one\n");
+            let err = meter.read_line(&mut line).unwrap_err();
+            assert_eq!(limit_error(&err), LimitExceeded { limit: 12, consumed: 12 });
+            assert_eq!(meter.get_ref(), b"e two\n");
+        }
+
+        #[test]
+        fn test_seek_frees_quota() {
+            let mut meter = MeteringReader::new(io::Cursor::new(vec![0u8; 10]));
+            meter.set_seek_policy(SeekPolicy::Reset);
+            meter.set_limit(Some(8));
+            let mut buf = [0u8; 8];
+            meter.read_exact(&mut buf).unwrap();
+            meter.seek(SeekFrom::Start(0)).unwrap();
+            meter.read_exact(&mut buf).unwrap();
+            assert!(meter.read(&mut buf).is_err());
+            // the held back byte is not part of the position
+            assert_eq!(meter.stream_position().unwrap(), 8);
+        }
+    }
+
+    mod test_meter_handle {
+        use std::io::{self, Read};
+        use std::sync::mpsc;
+        use std::thread;
+        use crate::{MeterHandle, MeteringReader, MeteringWriter, BlackHole};
+
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        #[test]
+        fn test_meter_handle_is_send_sync() {
+            assert_send_sync::<MeterHandle>();
+        }
+
+        // lets the test step through reads one at a time
+        struct SteppedReader {
+            steps: mpsc::Receiver<()>,
+        }
+
+        impl Read for SteppedReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.steps.recv() {
+                    Ok(()) => {
+                        buf[..2].copy_from_slice(b"ab");
+                        Ok(2)
+                    }
+                    Err(_) => Ok(0),
+                }
+            }
+        }
+
+        #[test]
+        fn test_meter_handle_polled_from_other_thread() {
+            let (step, steps) = mpsc::channel();
+            let (handles, handle_receiver) = mpsc::channel();
+            let copier = thread::spawn(move || {
+                let mut input = SteppedReader{ steps };
+                let mut meter = MeteringReader::new(&mut input);
+                handles.send(meter.handle()).unwrap();
+                io::copy(meter.as_reader(), &mut BlackHole{}).unwrap()
+            });
+            let handle = handle_receiver.recv().unwrap();
+            let observer = handle.clone();
+            assert_eq!(observer.get_counter(), 0);
+            for i in 1..=3 {
+                step.send(()).unwrap();
+                while observer.get_counter() < 2 * i {
+                    thread::yield_now();
+                }
+                assert_eq!(observer.get_counter(), 2 * i);
+            }
+            drop(step);
+            assert_eq!(copier.join().unwrap(), 6);
+            assert_eq!(handle.get_counter(), 6);
+        }
+
+        #[test]
+        fn test_meter_handle_outlives_meter() {
+            let handle;
+            {
+                let mut input = "123456".as_bytes();
+                let mut meter = MeteringReader::new(&mut input);
+                handle = meter.handle();
+                io::copy(meter.as_reader(), &mut BlackHole{}).unwrap();
+            }
+            assert_eq!(handle.get_counter(), 6);
+
+            let handle;
+            {
+                let mut sink = BlackHole{};
+                let mut meter = MeteringWriter::new(&mut sink);
+                handle = meter.handle();
+                io::copy(&mut "1234".as_bytes(), meter.as_writer()).unwrap();
+            }
+            assert_eq!(handle.get_counter(), 4);
+        }
+    }
+
+    mod test_metering_writer {
+        use std::io;
+        use super::TestWriter;
+        use crate::{MeteringWriter, BlackHole};
+
+        #[test]
+        fn test_metering_writer_update() {
+            let mut input = "123456".as_bytes();
+            let input_len = input.len();
+            let mut sink = BlackHole{};
+            let mut meter = MeteringWriter::new(&mut sink);
+            io::copy(&mut input, meter.as_writer()).unwrap();
+            assert_eq!(input_len, meter.get_counter());
+            assert_eq!(0, meter.get_flush_counter());
+            meter.as_writer().flush().unwrap();
+            meter.as_writer().flush().unwrap();
+            assert_eq!(2, meter.get_flush_counter());
+        }
+
+        #[test]
+        fn test_metering_writer_counts_accepted_bytes() {
+            let mut underlying = TestWriter::new().chunk(3);
+            {
+                let mut meter = MeteringWriter::new(&mut underlying);
+                assert_eq!(meter.as_writer().write(b"12345").unwrap(), 3);
+                assert_eq!(meter.get_counter(), 3);
+                meter.as_writer().write_all(b"6789").unwrap();
+                assert_eq!(meter.get_counter(), 7);
+            }
+            assert_eq!(underlying.data, b"1236789");
+        }
+
+        #[test]
+        fn test_metering_writer_errors_are_not_counted() {
+            let mut underlying = TestWriter::new().broken(io::ErrorKind::Other);
+            let mut meter = MeteringWriter::new(&mut underlying);
+            assert!(meter.as_writer().write(b"123").is_err());
+            assert!(meter.as_writer().flush().is_err());
+            assert_eq!(meter.get_counter(), 0);
+            assert_eq!(meter.get_flush_counter(), 0);
+        }
+    }
 }
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+// buffer size of pipe()
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+// Closed is why one end of the pipe went away.
+enum Closed {
+    Open,
+    // dropped or closed without an error
+    Done,
+    // closed with an error, which is handed to the other end once and then repeated by kind and message
+    Error(Option<io::Error>, io::ErrorKind, String),
+}
+
+impl Closed {
+    fn with_error(e: io::Error) -> Closed {
+        // an Interrupted error would be retried forever by read_to_end() and write_all()
+        let e = match e.kind() {
+            io::ErrorKind::Interrupted => io::Error::other(e),
+            _ => e,
+        };
+        let kind = e.kind();
+        let message = e.to_string();
+        Closed::Error(Some(e), kind, message)
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        match self {
+            Closed::Error(e, kind, message) => Some(e.take().unwrap_or_else(|| io::Error::new(*kind, message.clone()))),
+            _ => None,
+        }
+    }
+}
+
+struct State {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    reader: Closed,
+    writer: Closed,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // notified whenever the buffer or either end changes
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // the state stays consistent even if the other end panicked while holding the lock
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close_reader(&self, closed: Closed) {
+        let mut state = self.lock();
+        if let Closed::Open = state.reader {
+            state.reader = closed;
+        }
+        self.changed.notify_all();
+    }
+
+    fn close_writer(&self, closed: Closed) {
+        let mut state = self.lock();
+        if let Closed::Open = state.writer {
+            state.writer = closed;
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// pipe creates a synchronous in-memory pipe with a buffer of 64KiB, see pipe_with_capacity().
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    pipe_with_capacity(DEFAULT_CAPACITY)
+}
+
+/// pipe_with_capacity creates a synchronous in-memory pipe buffering up to `capacity` bytes,
+/// to connect a writer and a reader on different threads.
+///
+/// Reads block while the pipe is empty, writes block while it is full. Once the writer is dropped,
+/// reads return the buffered data and then EOF. Once the reader is dropped, writes fail with BrokenPipe.
+/// Either end can instead be closed with an error, which the other end gets in place of EOF or BrokenPipe.
+///
+/// A runtime panic will be thrown if capacity == 0.
+pub fn pipe_with_capacity(capacity: usize) -> (PipeReader, PipeWriter) {
+    if capacity == 0 { panic!("pipe capacity can not be 0") };
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            reader: Closed::Open,
+            writer: Closed::Open,
+        }),
+        changed: Condvar::new(),
+    });
+    (PipeReader { shared: Arc::clone(&shared) }, PipeWriter { shared })
+}
+
+/// PipeReader is the reading end of a pipe, see pipe_with_capacity().
+pub struct PipeReader {
+    shared: Arc<Shared>,
+}
+
+impl PipeReader {
+    /// Closes the pipe, writes fail with `e` (once, then with errors of the same kind and message).
+    /// An Interrupted error is wrapped in an error of kind Other.
+    pub fn close_with_error(self, e: io::Error) {
+        self.shared.close_reader(Closed::with_error(e));
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.shared.lock();
+        loop {
+            if !state.buffer.is_empty() {
+                let size = state.buffer.len().min(buf.len());
+                for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..size)) {
+                    *dst = src;
+                }
+                self.shared.changed.notify_all();
+                return Ok(size);
+            }
+            match state.writer {
+                Closed::Open => state = self.shared.wait(state),
+                Closed::Done => return Ok(0),
+                Closed::Error(..) => return Err(state.writer.take_error().unwrap()),
+            }
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.shared.close_reader(Closed::Done);
+    }
+}
+
+/// PipeWriter is the writing end of a pipe, see pipe_with_capacity().
+pub struct PipeWriter {
+    shared: Arc<Shared>,
+}
+
+impl PipeWriter {
+    /// Closes the pipe, reads fail with `e` after the buffered data (once, then with errors of the same kind and message).
+    /// An Interrupted error is wrapped in an error of kind Other.
+    pub fn close_with_error(self, e: io::Error) {
+        self.shared.close_writer(Closed::with_error(e));
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.shared.lock();
+        loop {
+            match state.reader {
+                Closed::Open => (),
+                Closed::Done => return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe reader is closed")),
+                Closed::Error(..) => return Err(state.reader.take_error().unwrap()),
+            }
+            let available = state.capacity - state.buffer.len();
+            if available > 0 {
+                let size = available.min(buf.len());
+                state.buffer.extend(&buf[..size]);
+                self.shared.changed.notify_all();
+                return Ok(size);
+            }
+            state = self.shared.wait(state);
+        }
+    }
+
+    /// Data is handed over on write, flush only checks that the reader is still there.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        match state.reader {
+            Closed::Open => Ok(()),
+            Closed::Done => Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe reader is closed")),
+            Closed::Error(..) => Err(state.reader.take_error().unwrap()),
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.shared.close_writer(Closed::Done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod test_pipe {
+        use crate::pipe::{pipe, pipe_with_capacity};
+        use std::io::{self, Read, Write};
+        use std::thread;
+
+        #[test]
+        fn test_transfer() {
+            let (mut reader, mut writer) = pipe_with_capacity(7);
+            let producer = thread::spawn(move || {
+                for i in 0..1000u32 {
+                    writer.write_all(&i.to_le_bytes()).unwrap();
+                }
+            });
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            producer.join().unwrap();
+            let expected: Vec<u8> = (0..1000u32).flat_map(|i| i.to_le_bytes()).collect();
+            assert_eq!(out, expected);
+        }
+
+        #[test]
+        fn test_bounded() {
+            let (mut reader, mut writer) = pipe_with_capacity(4);
+            assert_eq!(writer.write(b"0123456").unwrap(), 4);
+            let mut buf = [0u8; 3];
+            assert_eq!(reader.read(&mut buf).unwrap(), 3);
+            assert_eq!(writer.write(b"456").unwrap(), 3);
+            drop(writer);
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"3456");
+        }
+
+        #[test]
+        fn test_broken_pipe() {
+            let (reader, mut writer) = pipe();
+            writer.write_all(b"unread").unwrap();
+            drop(reader);
+            let err = writer.write(b"more").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            assert_eq!(writer.flush().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        }
+
+        #[test]
+        fn test_blocked_writer_sees_reader_drop() {
+            let (reader, mut writer) = pipe_with_capacity(2);
+            let producer = thread::spawn(move || writer.write_all(b"more than fits"));
+            drop(reader);
+            assert_eq!(producer.join().unwrap().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        }
+
+        #[test]
+        fn test_writer_close_with_error() {
+            let (mut reader, mut writer) = pipe();
+            writer.write_all(b"partial").unwrap();
+            writer.close_with_error(io::Error::new(io::ErrorKind::ConnectionAborted, "upstream failed"));
+            let mut out = Vec::new();
+            let err = reader.read_to_end(&mut out).unwrap_err();
+            assert_eq!(out, b"partial");
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+            assert_eq!(err.to_string(), "upstream failed");
+            let err = reader.read(&mut [0u8; 4]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+            assert_eq!(err.to_string(), "upstream failed");
+        }
+
+        #[test]
+        fn test_reader_close_with_error() {
+            let (reader, mut writer) = pipe_with_capacity(2);
+            let producer = thread::spawn(move || writer.write_all(b"more than fits"));
+            reader.close_with_error(io::Error::new(io::ErrorKind::InvalidData, "bad header"));
+            let err = producer.join().unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        #[test]
+        fn test_close_with_interrupted() {
+            let (mut reader, writer) = pipe();
+            writer.close_with_error(io::Error::new(io::ErrorKind::Interrupted, "stopped"));
+            let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            assert_eq!(err.to_string(), "stopped");
+            assert_eq!(reader.read(&mut [0u8; 4]).unwrap_err().kind(), io::ErrorKind::Other);
+
+            let (reader, mut writer) = pipe();
+            reader.close_with_error(io::Error::new(io::ErrorKind::Interrupted, "stopped"));
+            assert_eq!(writer.write_all(b"data").unwrap_err().kind(), io::ErrorKind::Other);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::clock::{Clock, SystemClock};
+use std::time::{Duration, Instant};
+
+/// Progress is what a progress callback is told about a meter, see ProgressReporter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// The count of the meter.
+    pub bytes: usize,
+    /// The expected total count, if known.
+    pub total: Option<usize>,
+    /// Time since the reporter was attached to the meter.
+    pub elapsed: Duration,
+    /// bytes / total * 100, None if the total is unknown or 0.
+    pub percentage: Option<f64>,
+    /// Estimated time left at the average rate so far, None if the total is unknown, no rate is known yet
+    /// or the estimate does not fit in a Duration.
+    /// It is 0 once the total is reached.
+    pub eta: Option<Duration>,
+}
+
+type ProgressFn = Box<dyn FnMut(&Progress) + Send>;
+
+/// ProgressReporter calls back with the Progress of a meter every N bytes and/or every T duration.
+///
+/// Reports are made from the thread using the meter, right after a call that crossed an interval.
+/// Once the reader hits EOF a final report is made if the count changed since the last one.
+/// With neither interval set, every call reports.
+pub struct ProgressReporter {
+    callback: ProgressFn,
+    every_bytes: Option<usize>,
+    every: Option<Duration>,
+    total: Option<usize>,
+    clock: Box<dyn Clock>,
+
+    // set when attached to a meter
+    start: Option<Instant>,
+    start_bytes: usize,
+    last_bytes: usize,
+    last_time: Option<Instant>,
+}
+
+impl ProgressReporter {
+    pub fn new<F: FnMut(&Progress) + Send + 'static>(callback: F) -> ProgressReporter {
+        ProgressReporter {
+            callback: Box::new(callback),
+            every_bytes: None,
+            every: None,
+            total: None,
+            clock: Box::new(SystemClock),
+            start: None,
+            start_bytes: 0,
+            last_bytes: 0,
+            last_time: None,
+        }
+    }
+
+    /// Reports whenever the count moved by `bytes` since the last report.
+    ///
+    /// A runtime panic will be thrown if bytes == 0.
+    pub fn every_bytes(mut self, bytes: usize) -> ProgressReporter {
+        if bytes == 0 { panic!("progress interval can not be empty") };
+        self.every_bytes = Some(bytes);
+        self
+    }
+
+    /// Reports whenever `duration` passed since the last report.
+    pub fn every(mut self, duration: Duration) -> ProgressReporter {
+        self.every = Some(duration);
+        self
+    }
+
+    /// Sets the count expected at the end, which gives percentage and ETA.
+    pub fn expected_total(mut self, total: usize) -> ProgressReporter {
+        self.total = Some(total);
+        self
+    }
+
+    /// Times the reports with `clock` instead of the SystemClock.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> ProgressReporter {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    // starts timing, counting intervals from the current count of the meter.
+    pub(crate) fn attach(&mut self, bytes: usize) {
+        let now = self.clock.now();
+        self.start = Some(now);
+        self.last_time = Some(now);
+        self.start_bytes = bytes;
+        self.last_bytes = bytes;
+    }
+
+    // called after each call on the meter with its count, eof forces a report of a changed count.
+    pub(crate) fn update(&mut self, bytes: usize, eof: bool) {
+        let now = self.clock.now();
+        let last_time = self.last_time.unwrap_or(now);
+        let due = match (self.every_bytes, self.every) {
+            (None, None) => true,
+            (every_bytes, every) => {
+                every_bytes.is_some_and(|every_bytes| bytes.abs_diff(self.last_bytes) >= every_bytes)
+                    || every.is_some_and(|every| now.saturating_duration_since(last_time) >= every)
+            }
+        };
+        let last = eof && bytes != self.last_bytes;
+        if !(due || last) {
+            return;
+        }
+        self.last_bytes = bytes;
+        self.last_time = Some(now);
+        let progress = self.progress(bytes, now);
+        (self.callback)(&progress);
+    }
+
+    fn progress(&self, bytes: usize, now: Instant) -> Progress {
+        let elapsed = now.saturating_duration_since(self.start.unwrap_or(now));
+        let percentage = match self.total {
+            Some(total) if total > 0 => Some(bytes as f64 / total as f64 * 100.0),
+            _ => None,
+        };
+        let eta = match self.total {
+            Some(total) if bytes >= total => Some(Duration::from_secs(0)),
+            Some(total) if bytes > self.start_bytes && !elapsed.is_zero() => {
+                // remaining bytes at the average rate since attached
+                let ratio = (total - bytes) as f64 / (bytes - self.start_bytes) as f64;
+                Duration::try_from_secs_f64(elapsed.as_secs_f64() * ratio).ok()
+            }
+            _ => None,
+        };
+        Progress { bytes, total: self.total, elapsed, percentage, eta }
+    }
+}
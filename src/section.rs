@@ -0,0 +1,220 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// ReadAt reads at a given offset without moving any cursor, like pread(2) or Go's io.ReaderAt.
+///
+/// Taking `&self`, it can be shared by readers of different parts of the same data, see SectionReader.
+/// A return of Ok(0) with a non empty buffer means offset is at or past the end.
+pub trait ReadAt {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let data = &self[offset as usize..];
+        let size = data.len().min(buf.len());
+        buf[..size].copy_from_slice(&data[..size]);
+        Ok(size)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Arc<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+/// SectionReader reads the window of `len` bytes at `offset` of a ReadAt, like Go's io.SectionReader.
+///
+/// It has a cursor of its own, positions are relative to the start of the window, and reading stops
+/// at its end. As ReadAt does not move a shared cursor, many sections of one `&File` or `Arc<File>`
+/// can be read at the same time, also from different threads.
+pub struct SectionReader<R> {
+    underlying_reader: R,
+    base: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: ReadAt> SectionReader<R> {
+    pub fn new(r: R, offset: u64, len: u64) -> SectionReader<R> {
+        // the window can not reach past the largest offset
+        let len = len.min(u64::MAX - offset);
+        SectionReader { underlying_reader: r, base: offset, len, pos: 0 }
+    }
+
+    /// Returns the length of the window.
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the offset of the window in the underlying reader.
+    pub fn offset(&self) -> u64 {
+        self.base
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.underlying_reader
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.underlying_reader
+    }
+}
+
+impl<R: ReadAt> ReadAt for SectionReader<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len {
+            return Ok(0);
+        }
+        let max = (self.len - offset).min(buf.len() as u64) as usize;
+        self.underlying_reader.read_at(&mut buf[..max], self.base + offset)
+    }
+}
+
+impl<R: ReadAt> Read for SectionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let size = self.read_at(buf, self.pos)?;
+        self.pos += size as u64;
+        Ok(size)
+    }
+}
+
+impl<R: ReadAt> Seek for SectionReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+        let (from, delta) = match pos {
+            SeekFrom::Start(pos) => {
+                self.pos = pos;
+                return Ok(pos);
+            }
+            SeekFrom::Current(delta) => (self.pos, delta),
+            SeekFrom::End(delta) => (self.len, delta),
+        };
+        match from.checked_add_signed(delta) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod test_section_reader {
+        use crate::section::{ReadAt, SectionReader};
+        use std::io::{self, Read, Seek, SeekFrom};
+        use std::sync::Arc;
+        use std::thread;
+
+        const DATA: &[u8] = b"0123456789abcdefghij";
+
+        #[test]
+        fn test_slice_read_at() {
+            let mut buf = [0u8; 4];
+            assert_eq!(DATA.read_at(&mut buf, 8).unwrap(), 4);
+            assert_eq!(&buf, b"89ab");
+            assert_eq!(DATA.read_at(&mut buf, 18).unwrap(), 2);
+            assert_eq!(DATA.read_at(&mut buf, 20).unwrap(), 0);
+            assert_eq!(DATA.read_at(&mut buf, u64::MAX).unwrap(), 0);
+        }
+
+        #[test]
+        fn test_section() {
+            let mut section = SectionReader::new(DATA, 5, 10);
+            let mut out = String::new();
+            section.read_to_string(&mut out).unwrap();
+            assert_eq!(out, "56789abcde");
+            assert_eq!(section.size(), 10);
+
+            // a window past the end is cut short by the data
+            let mut section = SectionReader::new(DATA, 15, 10);
+            out.clear();
+            section.read_to_string(&mut out).unwrap();
+            assert_eq!(out, "fghij");
+
+            let mut buf = [0u8; 3];
+            let section = SectionReader::new(DATA, 5, 10);
+            assert_eq!(section.read_at(&mut buf, 8).unwrap(), 2);
+            assert_eq!(&buf[..2], b"de");
+        }
+
+        #[test]
+        fn test_seek() {
+            let mut section = SectionReader::new(DATA, 10, 5);
+            let mut buf = [0u8; 2];
+            assert_eq!(section.seek(SeekFrom::End(-2)).unwrap(), 3);
+            section.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"de");
+            assert_eq!(section.seek(SeekFrom::Current(-4)).unwrap(), 1);
+            section.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"bc");
+            assert_eq!(section.seek(SeekFrom::Start(100)).unwrap(), 100);
+            assert_eq!(section.read(&mut buf).unwrap(), 0);
+            let err = section.seek(SeekFrom::End(-6)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(section.stream_position().unwrap(), 100);
+        }
+
+        #[test]
+        fn test_concurrent_sections() {
+            let data = Arc::new(DATA.to_vec());
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let mut section = SectionReader::new(Arc::clone(&data), i * 5, 5);
+                    thread::spawn(move || {
+                        let mut out = Vec::new();
+                        section.read_to_end(&mut out).unwrap();
+                        out
+                    })
+                })
+                .collect();
+            let parts: Vec<Vec<u8>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            assert_eq!(parts.concat(), DATA);
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn test_file_sections() {
+            use std::io::Write;
+            let path = std::env::temp_dir().join(format!("easyio-section-{}", std::process::id()));
+            std::fs::File::create(&path).unwrap().write_all(DATA).unwrap();
+            let file = std::fs::File::open(&path).unwrap();
+
+            let mut first = SectionReader::new(&file, 0, 4);
+            let mut second = SectionReader::new(&file, 16, 4);
+            let mut buf = [0u8; 2];
+            first.read_exact(&mut buf).unwrap();
+            second.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"gh");
+            first.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"23");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
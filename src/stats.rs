@@ -0,0 +1,111 @@
+use crate::clock::Clock;
+use std::time::{Duration, Instant};
+
+// time constant of the moving average rate
+const MOVING_AVERAGE_TIME: Duration = Duration::from_secs(5);
+
+/// MeterStats is a snapshot of the statistics collected by a meter, see MeteringReader::enable_stats().
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeterStats {
+    /// Bytes transferred since statistics were enabled.
+    pub bytes: usize,
+    /// Number of successful read or write calls.
+    pub calls: usize,
+    /// Time since statistics were enabled.
+    pub elapsed: Duration,
+    /// Bytes per second over the whole elapsed time.
+    pub average_rate: f64,
+    /// Bytes per second between the two most recent calls that were apart in time.
+    pub instant_rate: f64,
+    /// Exponential moving average of bytes per second, with a time constant of 5 seconds.
+    /// It decays while no data is transferred.
+    pub moving_rate: f64,
+    /// Number of calls by size: bucket 0 counts calls transferring 0 bytes,
+    /// bucket i counts calls transferring 2^(i-1) to 2^i - 1 bytes.
+    pub size_histogram: Vec<usize>,
+}
+
+// StatsRecorder collects MeterStats, a meter calls record() for each successful call.
+pub(crate) struct StatsRecorder {
+    clock: Box<dyn Clock>,
+    start: Instant,
+    bytes: usize,
+    calls: usize,
+    histogram: Vec<usize>,
+
+    // the rates are updated whenever time moved since last_sample, with the bytes recorded in between
+    last_sample: Instant,
+    unsampled: usize,
+    instant_rate: f64,
+    moving_rate: Option<f64>,
+}
+
+impl StatsRecorder {
+    pub fn new(clock: Box<dyn Clock>) -> StatsRecorder {
+        let start = clock.now();
+        StatsRecorder {
+            clock,
+            start,
+            bytes: 0,
+            calls: 0,
+            histogram: vec![0; usize::BITS as usize + 1],
+            last_sample: start,
+            unsampled: 0,
+            instant_rate: 0.0,
+            moving_rate: None,
+        }
+    }
+
+    pub fn record(&mut self, size: usize) {
+        self.bytes += size;
+        self.calls += 1;
+        self.histogram[(usize::BITS - size.leading_zeros()) as usize] += 1;
+        self.unsampled += size;
+
+        let now = self.clock.now();
+        let dt = now.saturating_duration_since(self.last_sample).as_secs_f64();
+        if dt > 0.0 {
+            self.instant_rate = self.unsampled as f64 / dt;
+            self.moving_rate = Some(moving_average(self.moving_rate, self.instant_rate, dt));
+            self.last_sample = now;
+            self.unsampled = 0;
+        }
+    }
+
+    pub fn snapshot(&self) -> MeterStats {
+        let now = self.clock.now();
+        let elapsed = now.saturating_duration_since(self.start);
+        let average_rate = if elapsed > Duration::from_secs(0) {
+            self.bytes as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        };
+        // bring the moving average up to now, as if the bytes not yet sampled arrived just now
+        let dt = now.saturating_duration_since(self.last_sample).as_secs_f64();
+        let moving_rate = if dt > 0.0 {
+            moving_average(self.moving_rate, self.unsampled as f64 / dt, dt)
+        } else {
+            self.moving_rate.unwrap_or(0.0)
+        };
+        MeterStats {
+            bytes: self.bytes,
+            calls: self.calls,
+            elapsed,
+            average_rate,
+            instant_rate: self.instant_rate,
+            moving_rate,
+            size_histogram: self.histogram.clone(),
+        }
+    }
+}
+
+// time weighted exponential moving average, so irregular samples are weighed by how long they cover
+fn moving_average(previous: Option<f64>, rate: f64, dt: f64) -> f64 {
+    match previous {
+        Some(previous) => {
+            let weight = 1.0 - (-dt / MOVING_AVERAGE_TIME.as_secs_f64()).exp();
+            previous + weight * (rate - previous)
+        }
+        None => rate,
+    }
+}
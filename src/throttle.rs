@@ -0,0 +1,272 @@
+use crate::clock::{Clock, Sleeper, SystemClock};
+use crate::{MeterHandle, MeteringReader, MeteringWriter};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+// shared by the clock and the sleeper of a throttle
+trait Timer: Clock + Sleeper {}
+impl<T: Clock + Sleeper> Timer for T {}
+
+// TokenBucket holds up to `burst` tokens of one byte each, refilled at `rate` tokens per second.
+struct TokenBucket {
+    rate: f64,
+    burst: usize,
+    tokens: f64,
+    last_refill: Instant,
+    timer: Box<dyn Timer>,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64, burst: usize, timer: Box<dyn Timer>) -> TokenBucket {
+        if bytes_per_sec == 0 { panic!("rate can not be 0") };
+        if burst == 0 { panic!("burst can not be 0") };
+
+        TokenBucket {
+            rate: bytes_per_sec as f64,
+            burst,
+            tokens: burst as f64,
+            last_refill: timer.now(),
+            timer,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = self.timer.now();
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst as f64);
+        self.last_refill = now;
+    }
+
+    // sleeps until min(want, burst) tokens are available, and returns that amount.
+    fn acquire(&mut self, want: usize) -> usize {
+        let want = want.min(self.burst);
+        loop {
+            self.refill();
+            let missing = want as f64 - self.tokens;
+            if missing <= 0.0 {
+                return want;
+            }
+            // round up, so that the bucket surely fills and the sleep is never 0
+            let nanos = (missing / self.rate * 1e9).ceil().max(1.0);
+            self.timer.sleep(Duration::from_nanos(nanos as u64));
+        }
+    }
+
+    fn spend(&mut self, size: usize) {
+        self.tokens -= size as f64;
+    }
+}
+
+/// ThrottledReader wraps around a reader and limits the rate of bytes read from it, using a token bucket.
+///
+/// Up to `burst` bytes can be read at once, after that reads wait for the bucket to refill at
+/// `bytes_per_sec`. A single read returns at most `burst` bytes. Tokens are waited for before the size
+/// of a read is known, so the read meeting EOF may wait as well; only the bytes actually read are charged.
+/// Bytes are counted by a MeteringReader, which is what the bucket is charged for.
+///
+/// A runtime panic will be thrown if bytes_per_sec == 0 or burst == 0.
+pub struct ThrottledReader<R> {
+    meter: MeteringReader<R>,
+    bucket: TokenBucket,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(r: R, bytes_per_sec: u64, burst: usize) -> ThrottledReader<R> {
+        ThrottledReader::with_clock(r, bytes_per_sec, burst, SystemClock)
+    }
+
+    /// Creates a throttled reader telling the time and waiting with `clock`, e.g. a ManualClock in tests.
+    pub fn with_clock<C: Clock + Sleeper + 'static>(r: R, bytes_per_sec: u64, burst: usize, clock: C) -> ThrottledReader<R> {
+        ThrottledReader {
+            meter: MeteringReader::new(r),
+            bucket: TokenBucket::new(bytes_per_sec, burst, Box::new(clock)),
+        }
+    }
+
+    pub fn get_counter(&self) -> usize {
+        self.meter.get_counter()
+    }
+
+    /// Returns a handle to the counter which can be polled while the reader is in use.
+    pub fn handle(&self) -> MeterHandle {
+        self.meter.handle()
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.meter.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying reader, reading from it directly is not throttled.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.meter.get_mut()
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.meter.into_inner()
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if buf.is_empty() {
+            return self.meter.read(buf);
+        }
+        let allowed = self.bucket.acquire(buf.len());
+        let before = self.meter.get_counter();
+        let result = self.meter.read(&mut buf[..allowed]);
+        self.bucket.spend(self.meter.get_counter().saturating_sub(before));
+        result
+    }
+}
+
+/// ThrottledWriter wraps around a writer and limits the rate of bytes written to it, using a token bucket.
+///
+/// Up to `burst` bytes can be written at once, after that writes wait for the bucket to refill at
+/// `bytes_per_sec`. A single write accepts at most `burst` bytes.
+/// Bytes are counted by a MeteringWriter, which is what the bucket is charged for.
+///
+/// A runtime panic will be thrown if bytes_per_sec == 0 or burst == 0.
+pub struct ThrottledWriter<W> {
+    meter: MeteringWriter<W>,
+    bucket: TokenBucket,
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    pub fn new(w: W, bytes_per_sec: u64, burst: usize) -> ThrottledWriter<W> {
+        ThrottledWriter::with_clock(w, bytes_per_sec, burst, SystemClock)
+    }
+
+    /// Creates a throttled writer telling the time and waiting with `clock`, e.g. a ManualClock in tests.
+    pub fn with_clock<C: Clock + Sleeper + 'static>(w: W, bytes_per_sec: u64, burst: usize, clock: C) -> ThrottledWriter<W> {
+        ThrottledWriter {
+            meter: MeteringWriter::new(w),
+            bucket: TokenBucket::new(bytes_per_sec, burst, Box::new(clock)),
+        }
+    }
+
+    pub fn get_counter(&self) -> usize {
+        self.meter.get_counter()
+    }
+
+    /// Returns a handle to the counter which can be polled while the writer is in use.
+    pub fn handle(&self) -> MeterHandle {
+        self.meter.handle()
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.meter.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying writer, writing to it directly is not throttled.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.meter.get_mut()
+    }
+
+    /// Unwraps this writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.meter.into_inner()
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.meter.write(buf);
+        }
+        let allowed = self.bucket.acquire(buf.len());
+        let size = self.meter.write(&buf[..allowed])?;
+        self.bucket.spend(size);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.meter.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod test_throttle {
+        use crate::throttle::{ThrottledReader, ThrottledWriter};
+        use crate::clock::{Clock, ManualClock};
+        use std::io::{self, Read, Write};
+        use std::time::Duration;
+
+        #[test]
+        fn test_reader_rate() {
+            let clock = ManualClock::new();
+            let start = clock.now();
+            let mut reader = ThrottledReader::with_clock(io::repeat(1).take(10000), 1000, 100, clock.clone());
+            let mut buf = [0u8; 250];
+
+            // the full bucket is served at once, up to burst
+            assert_eq!(reader.read(&mut buf).unwrap(), 100);
+            assert_eq!(clock.now() - start, Duration::from_secs(0));
+
+            let mut out = Vec::new();
+            io::copy(&mut reader, &mut out).unwrap();
+            assert_eq!(out.len(), 9900);
+            assert_eq!(reader.get_counter(), 10000);
+            // the first 100 bytes were free, the rest took 9.9 seconds, and the read meeting EOF waited for a burst
+            let elapsed = (clock.now() - start).as_secs_f64();
+            assert!((elapsed - 10.0).abs() < 0.001, "{}", elapsed);
+        }
+
+        #[test]
+        fn test_bucket_refills_while_idle() {
+            let clock = ManualClock::new();
+            let mut reader = ThrottledReader::with_clock(io::repeat(1), 10, 50, clock.clone());
+            let mut buf = [0u8; 50];
+            reader.read_exact(&mut buf).unwrap();
+
+            // 2 seconds idle refill 20 tokens
+            clock.advance(Duration::from_secs(2));
+            let start = clock.now();
+            assert_eq!(reader.read(&mut buf[..20]).unwrap(), 20);
+            assert_eq!(clock.now(), start);
+
+            // the bucket never holds more than burst
+            clock.advance(Duration::from_secs(100));
+            let start = clock.now();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(clock.now(), start);
+            assert_eq!(reader.read(&mut buf[..1]).unwrap(), 1);
+            assert_eq!(clock.now() - start, Duration::from_millis(100));
+        }
+
+        #[test]
+        fn test_short_reads_are_charged_by_count() {
+            let clock = ManualClock::new();
+            let start = clock.now();
+            let mut reader = ThrottledReader::with_clock(&b"abc"[..], 1, 10, clock.clone());
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            assert_eq!(out, b"abc");
+            // 3 bytes of a 10 bytes bucket, EOF costs nothing
+            assert_eq!(clock.now() - start, Duration::from_secs(0));
+            assert_eq!(reader.handle().get_counter(), 3);
+        }
+
+        #[test]
+        fn test_writer_rate() {
+            let clock = ManualClock::new();
+            let start = clock.now();
+            let mut writer = ThrottledWriter::with_clock(Vec::new(), 100, 10, clock.clone());
+            writer.write_all(&[7u8; 1010]).unwrap();
+            writer.flush().unwrap();
+            assert_eq!(writer.get_counter(), 1010);
+            assert_eq!(writer.get_ref().len(), 1010);
+            let elapsed = (clock.now() - start).as_secs_f64();
+            assert!((elapsed - 10.0).abs() < 0.001, "{}", elapsed);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_zero_rate() {
+            ThrottledWriter::new(Vec::new(), 0, 10);
+        }
+    }
+}